@@ -0,0 +1,97 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// The most distinct values of the `category` label reported per experiment,
+/// for mismatches and errors separately. Later categories are reported as
+/// `other`.
+pub(crate) const MAX_CATEGORIES: usize = 16;
+
+/// The most distinct values of each label set with `Experiment::label`
+/// reported per experiment. Later values are reported as `__overflow__`.
+pub(crate) const MAX_LABEL_VALUES: usize = 100;
+
+/// A label whose values are limited separately from the others
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Budget {
+    /// The `category` label of mismatches
+    MismatchCategory,
+
+    /// The `category` label of errors
+    ErrorCategory,
+
+    /// A label set with `Experiment::label`, by key
+    Label(&'static str),
+}
+
+/// Distinct values seen so far, per experiment name and label
+type Seen = HashMap<(&'static str, Budget), HashSet<String>>;
+
+static SEEN: Mutex<Option<Seen>> = Mutex::new(None);
+
+/// Whether `value` is one of the first `limit` distinct values seen for `budget`
+/// of the named experiment
+fn admit(name: &'static str, budget: Budget, value: &str, limit: usize) -> bool {
+    let mut seen = SEEN.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let values = seen
+        .get_or_insert_with(HashMap::new)
+        .entry((name, budget))
+        .or_default();
+
+    if values.contains(value) {
+        true
+    } else if values.len() < limit {
+        values.insert(value.to_string());
+        true
+    } else {
+        false
+    }
+}
+
+/// Limit the `category` label values of mismatches to `MAX_CATEGORIES` per
+/// experiment
+pub(crate) fn mismatch_category(name: &'static str, category: &'static str) -> &'static str {
+    limit_category(name, Budget::MismatchCategory, category)
+}
+
+/// Limit the `category` label values of errors to `MAX_CATEGORIES` per
+/// experiment
+pub(crate) fn error_category(name: &'static str, category: &'static str) -> &'static str {
+    limit_category(name, Budget::ErrorCategory, category)
+}
+
+fn limit_category(name: &'static str, budget: Budget, category: &'static str) -> &'static str {
+    if admit(name, budget, category, MAX_CATEGORIES) {
+        category
+    } else {
+        "other"
+    }
+}
+
+/// Limit the values of a label set with `Experiment::label` to
+/// `MAX_LABEL_VALUES` per experiment
+pub(crate) fn label(name: &'static str, key: &'static str, value: &str) -> String {
+    if admit(name, Budget::Label(key), value, MAX_LABEL_VALUES) {
+        value.to_string()
+    } else {
+        "__overflow__".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_limits_mismatch_and_error_categories_separately() {
+        const CATEGORIES: [&str; 17] = [
+            "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q",
+        ];
+
+        for category in &CATEGORIES[..16] {
+            assert_eq!(mismatch_category("budgets", category), *category);
+        }
+        assert_eq!(mismatch_category("budgets", "q"), "other");
+
+        assert_eq!(error_category("budgets", "q"), "q");
+    }
+}
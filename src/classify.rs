@@ -0,0 +1,19 @@
+/// An `ErrorClassifier` maps an error to a label from a small, fixed set, like
+/// `timeout`, `not_found` or `internal`. It is given to
+/// `Experiment::classify_error`, and the label is added as the `category` label
+/// of `thesis_experiment_outcome`.
+///
+/// Closures taking a reference to the error implement this trait, so a method
+/// on the error type such as `MyError::category` can be passed directly.
+pub trait ErrorClassifier<E> {
+    fn classify(&self, error: &E) -> &'static str;
+}
+
+impl<F, E> ErrorClassifier<E> for F
+where
+    F: Fn(&E) -> &'static str,
+{
+    fn classify(&self, error: &E) -> &'static str {
+        self(error)
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Number of experimental futures currently in flight, per experiment name
+static IN_FLIGHT: Mutex<Option<HashMap<&'static str, Arc<AtomicUsize>>>> = Mutex::new(None);
+
+/// Held while an experimental future is in flight, releasing its slot when
+/// dropped
+pub(crate) struct Permit {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Take one of the `limit` slots for experimental futures of the named
+/// experiment, or `None` if all of them are taken
+pub(crate) fn try_acquire(name: &'static str, limit: usize) -> Option<Permit> {
+    let in_flight = IN_FLIGHT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .entry(name)
+        .or_default()
+        .clone();
+
+    in_flight
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+            if current < limit {
+                Some(current + 1)
+            } else {
+                None
+            }
+        })
+        .ok()
+        .map(|_| Permit { in_flight })
+}
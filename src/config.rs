@@ -0,0 +1,232 @@
+//! Global configuration of the metrics reported by thesis. By default, metrics
+//! have the names listed in the crate README and durations are reported in
+//! seconds. `set_metrics_config` changes this for the default observer, for
+//! `OtelObserver`s created afterwards, and for `thesis::prometheus`.
+//!
+//! ```
+//! use thesis::config::{self, DurationUnit, MetricsConfig};
+//!
+//! config::set_metrics_config(
+//!     MetricsConfig::new()
+//!         .prefix("payments")
+//!         .rename("thesis_experiment_outcome", "thesis_experiment_outcome_total")
+//!         .duration_unit(DurationUnit::Milliseconds),
+//! );
+//!
+//! let config = config::metrics_config();
+//! assert_eq!(
+//!     config.name("thesis_experiment_outcome"),
+//!     "payments_thesis_experiment_outcome_total"
+//! );
+//! # config::set_metrics_config(MetricsConfig::new());
+//! ```
+
+#[cfg(feature = "metrics")]
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
+/// The unit durations are reported in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DurationUnit {
+    #[default]
+    Seconds,
+    Milliseconds,
+}
+
+impl DurationUnit {
+    /// The UCUM symbol of the unit, `s` or `ms`
+    pub fn symbol(self) -> &'static str {
+        match self {
+            DurationUnit::Seconds => "s",
+            DurationUnit::Milliseconds => "ms",
+        }
+    }
+
+    /// Convert a number of seconds to this unit
+    pub fn from_secs(self, seconds: f64) -> f64 {
+        match self {
+            DurationUnit::Seconds => seconds,
+            DurationUnit::Milliseconds => seconds * 1000.0,
+        }
+    }
+}
+
+/// The names and units of the metrics reported by thesis
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct MetricsConfig {
+    /// Prepended to the name of every metric, followed by an underscore
+    pub prefix: Option<String>,
+
+    /// Names to report metrics under instead of their default names, by
+    /// default name. The prefix is still prepended.
+    pub names: HashMap<String, String>,
+
+    /// The unit of `thesis_experiment_run_duration` and
+    /// `thesis_experiment_latency_delta`
+    pub duration_unit: DurationUnit,
+}
+
+impl MetricsConfig {
+    /// Create the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prepend `prefix` and an underscore to the name of every metric
+    pub fn prefix(self, prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: Some(prefix.into()),
+            ..self
+        }
+    }
+
+    /// Report the metric with the given default name as `name` instead
+    pub fn rename(mut self, default_name: &str, name: impl Into<String>) -> Self {
+        self.names.insert(default_name.to_string(), name.into());
+        self
+    }
+
+    /// Report durations in the given unit
+    pub fn duration_unit(self, duration_unit: DurationUnit) -> Self {
+        Self {
+            duration_unit,
+            ..self
+        }
+    }
+
+    /// The name to report the metric with the given default name under
+    pub fn name(&self, default_name: &str) -> String {
+        let name = self
+            .names
+            .get(default_name)
+            .map_or(default_name, String::as_str);
+
+        match &self.prefix {
+            Some(prefix) => format!("{}_{}", prefix, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// A duration in the configured unit
+    pub fn duration(&self, duration: Duration) -> f64 {
+        self.duration_unit.from_secs(duration.as_secs_f64())
+    }
+}
+
+static METRICS_CONFIG: RwLock<Option<Arc<MetricsConfig>>> = RwLock::new(None);
+
+/// Incremented by `set_metrics_config`, so threads know when the metric names
+/// they resolved are out of date
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Use the given configuration for every metric reported from now on.
+/// Replaces any previously set configuration.
+pub fn set_metrics_config(config: MetricsConfig) {
+    let mut current = METRICS_CONFIG
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    *current = Some(Arc::new(config));
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// The configuration set with `set_metrics_config`, or the default
+/// configuration if it hasn't been called
+pub fn metrics_config() -> Arc<MetricsConfig> {
+    static DEFAULT: OnceLock<Arc<MetricsConfig>> = OnceLock::new();
+
+    METRICS_CONFIG
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+        .unwrap_or_else(|| DEFAULT.get_or_init(Default::default).clone())
+}
+
+/// The names and unit the default observer reports metrics with, resolved from
+/// the metrics configuration once rather than for every metric
+#[cfg(feature = "metrics")]
+pub(crate) struct MetricNames {
+    pub(crate) run_total: Arc<str>,
+    pub(crate) run_variant: Arc<str>,
+    pub(crate) skipped: Arc<str>,
+    pub(crate) outcome: Arc<str>,
+    pub(crate) race_won: Arc<str>,
+    pub(crate) run_duration: Arc<str>,
+    pub(crate) latency_delta: Arc<str>,
+    pub(crate) duration_unit: DurationUnit,
+}
+
+#[cfg(feature = "metrics")]
+impl MetricNames {
+    fn new(config: &MetricsConfig) -> Self {
+        let name = |default_name| Arc::from(config.name(default_name));
+
+        Self {
+            run_total: name("thesis_experiment_run_total"),
+            run_variant: name("thesis_experiment_run_variant"),
+            skipped: name("thesis_experiment_skipped"),
+            outcome: name("thesis_experiment_outcome"),
+            race_won: name("thesis_experiment_race_won"),
+            run_duration: name("thesis_experiment_run_duration"),
+            latency_delta: name("thesis_experiment_latency_delta"),
+            duration_unit: config.duration_unit,
+        }
+    }
+
+    /// A duration in the configured unit
+    pub(crate) fn duration(&self, duration: Duration) -> f64 {
+        self.duration_unit.from_secs(duration.as_secs_f64())
+    }
+}
+
+#[cfg(feature = "metrics")]
+thread_local! {
+    /// The metric names each thread has resolved, along with the generation of
+    /// the configuration they were resolved from
+    static NAMES: RefCell<Option<(usize, Arc<MetricNames>)>> = const { RefCell::new(None) };
+}
+
+/// The metric names for the current configuration, which are only resolved
+/// again on each thread after `set_metrics_config` is called
+#[cfg(feature = "metrics")]
+pub(crate) fn metric_names() -> Arc<MetricNames> {
+    let generation = GENERATION.load(Ordering::Acquire);
+    let cached = NAMES
+        .try_with(|names| match &*names.borrow() {
+            Some((cached, names)) if *cached == generation => Some(names.clone()),
+            _ => None,
+        })
+        .ok()
+        .flatten();
+    if let Some(names) = cached {
+        return names;
+    }
+
+    let names = Arc::new(MetricNames::new(&metrics_config()));
+    let _ = NAMES.try_with(|cached| *cached.borrow_mut() = Some((generation, names.clone())));
+    names
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_deserializes_partial_configs() {
+        let config: MetricsConfig =
+            serde_json::from_str(r#"{"prefix": "payments", "duration_unit": "Milliseconds"}"#)
+                .unwrap();
+
+        assert_eq!(
+            config,
+            MetricsConfig::new()
+                .prefix("payments")
+                .duration_unit(DurationUnit::Milliseconds)
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use std::fmt;
+
+/// An error returned when thesis is given invalid input
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// A rollout percentage which is NaN or not between 0 and 100
+    InvalidPercent(f64),
+
+    /// A rollout in basis points which is over 10000
+    InvalidBasisPoints(u32),
+
+    /// An expiry date which doesn't exist or is before 1970, as a year, month
+    /// and day
+    InvalidDate(i32, u32, u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidPercent(percent) => {
+                write!(f, "percent must be between 0 and 100, got {}", percent)
+            }
+            Error::InvalidBasisPoints(basis_points) => write!(
+                f,
+                "basis points must be between 0 and 10000, got {}",
+                basis_points
+            ),
+            Error::InvalidDate(year, month, day) => write!(
+                f,
+                "expiry date must be a valid date from 1970 onwards, got {}-{}-{}",
+                year, month, day
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
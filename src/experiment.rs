@@ -1,12 +1,26 @@
-use metrics::{counter, histogram};
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use std::any::Any;
 use std::fmt::Display;
 use std::future::Future;
 use std::marker::PhantomData;
-use std::time::Instant;
-use tracing::{info_span, Instrument};
-
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cardinality;
+use crate::classify::ErrorClassifier;
+use crate::concurrency;
+use crate::latency;
+use crate::metadata::{self, Metadata};
 use crate::mismatch::{self, Mismatch, MismatchHandler};
-use crate::rollout::{RolloutDecision, RolloutStrategy};
+use crate::observer::{self, BranchOutcome, ExperimentObserver};
+use crate::report::{Outcome, Report, Returned};
+use crate::rollout::{self, RolloutDecision, RolloutStrategy};
+use crate::runtime::{self, Spawner};
+use crate::stats::StatsObserver;
+use crate::trace::{info_span, Instrument};
 
 /// An individual experiment. See crate-level documentation for an example on how
 /// to use
@@ -16,9 +30,25 @@ pub struct Experiment<T, C, E, R, M> {
     experimental_builder: E,
     rollout_strategy: R,
     mismatch_handler: M,
+    observer: Option<Arc<dyn ExperimentObserver>>,
+    experimental_concurrency_limit: Option<usize>,
+    mismatch_classifier: Option<MismatchClassifier<T>>,
+    error_classifier: Option<OutputClassifier<T>>,
+    labels: Vec<(&'static str, String)>,
     name: &'static str,
 }
 
+/// Labels which thesis reports itself, and which can't be set with
+/// `Experiment::label`
+const RESERVED_LABELS: [&str; 5] = ["name", "kind", "outcome", "reason", "category"];
+
+/// Function given to `Experiment::classify_mismatch`
+type MismatchClassifier<T> = Box<dyn Fn(&Mismatch<T>) -> &'static str + Send + Sync>;
+
+/// Classifies the errors in the `Result`s returned by an experiment's methods,
+/// see `Experiment::classify_error`
+type OutputClassifier<T> = Box<dyn Fn(&T) -> Option<&'static str> + Send + Sync>;
+
 impl<T> Experiment<T, (), (), (), mismatch::AlwaysControl> {
     /// Create a new experiment. The only provided default is accepting the
     /// control value in the mismatch handler. All other builder-style functions
@@ -31,15 +61,25 @@ impl<T> Experiment<T, (), (), (), mismatch::AlwaysControl> {
             experimental_builder: (),
             mismatch_handler: mismatch::AlwaysControl,
             rollout_strategy: (),
+            observer: None,
+            experimental_concurrency_limit: None,
+            mismatch_classifier: None,
+            error_classifier: None,
+            labels: Vec::new(),
         }
     }
 }
 
-async fn instrument_control<F, T>(name: &'static str, future: F) -> T
+async fn instrument_control<F, T>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    future: F,
+) -> (T, Duration)
 where
     F: Future<Output = T>,
 {
     measure_duration(
+        observer,
         name,
         "control",
         future.instrument(info_span!("Experiment::run control", method = "control")),
@@ -47,11 +87,16 @@ where
     .await
 }
 
-async fn instrument_experimental<F, T>(name: &'static str, future: F) -> T
+async fn instrument_experimental<F, T>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    future: F,
+) -> (T, Duration)
 where
     F: Future<Output = T>,
 {
     measure_duration(
+        observer,
         name,
         "experimental",
         future.instrument(info_span!(
@@ -62,22 +107,289 @@ where
     .await
 }
 
-async fn measure_duration<F, T>(name: &'static str, kind: &'static str, future: F) -> T
+async fn measure_duration<F, T>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    kind: &'static str,
+    future: F,
+) -> (T, Duration)
 where
     F: Future<Output = T>,
 {
+    let scope = observer.branch_scope(name, kind);
     let start = Instant::now();
-    let output = future.await;
+    let output = observer::scoped(scope, future).await;
     let duration = start.elapsed();
+    observer.on_branch_duration(name, kind, duration);
+
+    (output, duration)
+}
+
+async fn run_control_only<F, T>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    decision: RolloutDecision,
+    future: F,
+) -> (T, Report)
+where
+    F: Future<Output = T>,
+{
+    let (control, duration) = instrument_control(observer, name, future).await;
+
+    (
+        control,
+        Report {
+            decision,
+            control_duration: Some(duration),
+            experimental_duration: None,
+            outcome: Outcome::Ignored,
+            returned: Returned::Control,
+        },
+    )
+}
+
+async fn run_experimental_only<F, T>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    decision: RolloutDecision,
+    future: F,
+) -> (T, Report)
+where
+    F: Future<Output = T>,
+{
+    let (experimental, duration) = instrument_experimental(observer, name, future).await;
+
+    (
+        experimental,
+        Report {
+            decision,
+            control_duration: None,
+            experimental_duration: Some(duration),
+            outcome: Outcome::Ignored,
+            returned: Returned::Experimental,
+        },
+    )
+}
 
-    histogram!(
-        "thesis_experiment_run_duration",
-        "name" => name,
-        "kind" => kind,
+/// Run the closure on a blocking thread once the returned future is polled,
+/// spawned with the spawner if there is one, or as a new thread otherwise. A
+/// panic in the closure is resumed on the task polling the future.
+async fn spawn_blocking<F, T>(spawner: Option<Arc<dyn Spawner>>, f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    let f = move || {
+        let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(f)));
+    };
+    match spawner {
+        Some(spawner) => spawner.spawn_blocking(Box::new(f)),
+        None => {
+            thread::spawn(f);
+        }
+    }
+
+    match rx
+        .await
+        .expect("the spawner dropped the closure without running it")
+    {
+        Ok(output) => output,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Run the experimental future, catching a panic so the control can be run
+/// instead. The report assumes the control won't need to run.
+async fn run_experimental_catching_panics<F, T>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    decision: RolloutDecision,
+    future: F,
+) -> (Result<T, String>, Report)
+where
+    F: Future<Output = T>,
+{
+    let (experimental, report) = run_experimental_only(
+        observer,
+        name,
+        decision,
+        AssertUnwindSafe(future).catch_unwind(),
     )
-    .record(duration);
+    .await;
+
+    (experimental.map_err(panic_message), report)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned());
+
+    match message {
+        Some(message) => format!("panicked: {}", message),
+        None => "panicked".to_string(),
+    }
+}
+
+/// Run the control future after the experimental failed for the given reason
+async fn fall_back_to_control<F, T>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    future: F,
+    mut report: Report,
+    reason: &dyn Display,
+) -> (T, Report)
+where
+    F: Future<Output = T>,
+{
+    observer.on_fallback(name, reason);
+    let (control, duration) = instrument_control(observer, name, future).await;
+
+    report.control_duration = Some(duration);
+    report.outcome = Outcome::Fallback;
+    report.returned = Returned::Control;
+
+    (control, report)
+}
+
+/// Run the experimental future, and the control future only if the
+/// experimental panicked
+async fn run_with_fallback<C, E, T>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    decision: RolloutDecision,
+    control: C,
+    experimental: E,
+) -> (T, Report)
+where
+    C: Future<Output = T>,
+    E: Future<Output = T>,
+{
+    let (experimental, report) =
+        run_experimental_catching_panics(observer, name, decision, experimental).await;
+
+    match experimental {
+        Ok(experimental) => (experimental, report),
+        Err(panic) => fall_back_to_control(observer, name, control, report, &panic).await,
+    }
+}
+
+/// Run the experimental future, and the control future only if the
+/// experimental returned `Err` or panicked
+async fn run_result_with_fallback<C, E, T, Err>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    classifier: Option<&OutputClassifier<Result<T, Err>>>,
+    decision: RolloutDecision,
+    control: C,
+    experimental: E,
+) -> (Result<T, Err>, Report)
+where
+    C: Future<Output = Result<T, Err>>,
+    E: Future<Output = Result<T, Err>>,
+    Err: Display,
+{
+    let (experimental, report) =
+        run_experimental_catching_panics(observer, name, decision, experimental).await;
+
+    let (result, report) = match experimental {
+        Ok(result) => {
+            outcome(observer, name, "experimental", classifier, &result);
+
+            match result {
+                Ok(value) => return (Ok(value), report),
+                Err(error) => fall_back_to_control(observer, name, control, report, &error).await,
+            }
+        }
+        Err(panic) => {
+            // The classifier can only classify a `Result`, so a panic is
+            // reported without a category
+            let outcome = BranchOutcome::Error {
+                error: &panic,
+                category: None,
+            };
+            observer.on_outcome(name, "experimental", outcome);
+
+            fall_back_to_control(observer, name, control, report, &panic).await
+        }
+    };
+    outcome(observer, name, "control", classifier, &result);
+
+    (result, report)
+}
+
+/// The branch which finished first in `race`, along with the branch which is
+/// still running
+struct Race<T> {
+    winner: Returned,
+    output: T,
+    duration: Duration,
+    loser: BoxFuture<'static, (T, Duration)>,
+}
+
+impl<T> Race<T> {
+    fn winner_kind(&self) -> &'static str {
+        match self.winner {
+            Returned::Control => "control",
+            _ => "experimental",
+        }
+    }
 
-    output
+    fn loser_kind(&self) -> &'static str {
+        match self.winner {
+            Returned::Control => "experimental",
+            _ => "control",
+        }
+    }
+
+    /// Wait for the losing branch, returning the control and experimental
+    /// outputs in that order
+    async fn finish(self) -> ((T, Duration), (T, Duration)) {
+        let loser = self.loser.await;
+
+        match self.winner {
+            Returned::Control => ((self.output, self.duration), loser),
+            _ => (loser, (self.output, self.duration)),
+        }
+    }
+}
+
+async fn race<C, E, T>(
+    observer: &StatsObserver,
+    name: &'static str,
+    control: C,
+    experimental: E,
+) -> Race<T>
+where
+    C: Future<Output = T> + Send + 'static,
+    E: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let control = {
+        let observer = observer.clone();
+        async move { instrument_control(&observer, name, control).await }.boxed()
+    };
+    let experimental = {
+        let observer = observer.clone();
+        async move { instrument_experimental(&observer, name, experimental).await }.boxed()
+    };
+
+    match future::select(control, experimental).await {
+        Either::Left(((output, duration), loser)) => Race {
+            winner: Returned::Control,
+            output,
+            duration,
+            loser,
+        },
+        Either::Right(((output, duration), loser)) => Race {
+            winner: Returned::Experimental,
+            output,
+            duration,
+            loser,
+        },
+    }
 }
 
 impl<T, C, E, R, M> Experiment<T, C, E, R, M> {
@@ -94,6 +406,11 @@ impl<T, C, E, R, M> Experiment<T, C, E, R, M> {
             result_type: self.result_type,
             rollout_strategy: self.rollout_strategy,
             mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+            experimental_concurrency_limit: self.experimental_concurrency_limit,
+            mismatch_classifier: self.mismatch_classifier,
+            error_classifier: self.error_classifier,
+            labels: self.labels,
         }
     }
 
@@ -110,6 +427,53 @@ impl<T, C, E, R, M> Experiment<T, C, E, R, M> {
             control_builder: self.control_builder,
             rollout_strategy: self.rollout_strategy,
             mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+            experimental_concurrency_limit: self.experimental_concurrency_limit,
+            mismatch_classifier: self.mismatch_classifier,
+            error_classifier: self.error_classifier,
+            labels: self.labels,
+        }
+    }
+
+    /// Use the closure given here as the control, for experiments run with
+    /// `run_sync` or `run_result_sync`
+    pub fn control_fn<NC>(self, control_builder: NC) -> Experiment<T, NC, E, R, M>
+    where
+        NC: FnOnce() -> T,
+    {
+        Experiment {
+            control_builder,
+            name: self.name,
+            experimental_builder: self.experimental_builder,
+            result_type: self.result_type,
+            rollout_strategy: self.rollout_strategy,
+            mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+            experimental_concurrency_limit: self.experimental_concurrency_limit,
+            mismatch_classifier: self.mismatch_classifier,
+            error_classifier: self.error_classifier,
+            labels: self.labels,
+        }
+    }
+
+    /// Use the closure given here as the experimental, for experiments run
+    /// with `run_sync` or `run_result_sync`
+    pub fn experimental_fn<NE>(self, experimental_builder: NE) -> Experiment<T, C, NE, R, M>
+    where
+        NE: FnOnce() -> T,
+    {
+        Experiment {
+            experimental_builder,
+            name: self.name,
+            result_type: self.result_type,
+            control_builder: self.control_builder,
+            rollout_strategy: self.rollout_strategy,
+            mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+            experimental_concurrency_limit: self.experimental_concurrency_limit,
+            mismatch_classifier: self.mismatch_classifier,
+            error_classifier: self.error_classifier,
+            labels: self.labels,
         }
     }
 
@@ -122,6 +486,11 @@ impl<T, C, E, R, M> Experiment<T, C, E, R, M> {
             control_builder: self.control_builder,
             experimental_builder: self.experimental_builder,
             mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+            experimental_concurrency_limit: self.experimental_concurrency_limit,
+            mismatch_classifier: self.mismatch_classifier,
+            error_classifier: self.error_classifier,
+            labels: self.labels,
         }
     }
 
@@ -140,6 +509,121 @@ impl<T, C, E, R, M> Experiment<T, C, E, R, M> {
             result_type: self.result_type,
             control_builder: self.control_builder,
             experimental_builder: self.experimental_builder,
+            observer: self.observer,
+            experimental_concurrency_limit: self.experimental_concurrency_limit,
+            mismatch_classifier: self.mismatch_classifier,
+            error_classifier: self.error_classifier,
+            labels: self.labels,
+        }
+    }
+
+    /// Use the given `MismatchHandler` to resolve mismatches. See
+    /// `on_mismatch`.
+    pub fn mismatch_handler<NM>(self, mismatch_handler: NM) -> Experiment<T, C, E, R, NM>
+    where
+        NM: MismatchHandler<T>,
+    {
+        Experiment {
+            mismatch_handler,
+            name: self.name,
+            rollout_strategy: self.rollout_strategy,
+            result_type: self.result_type,
+            control_builder: self.control_builder,
+            experimental_builder: self.experimental_builder,
+            observer: self.observer,
+            experimental_concurrency_limit: self.experimental_concurrency_limit,
+            mismatch_classifier: self.mismatch_classifier,
+            error_classifier: self.error_classifier,
+            labels: self.labels,
+        }
+    }
+
+    /// Report this experiment to the given observer instead of the global
+    /// observer. See `observer::set_global_observer`.
+    pub fn observer(self, observer: Arc<dyn ExperimentObserver>) -> Self {
+        Experiment {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    /// Declare metadata describing this experiment, such as its owner and when
+    /// it expires. See `thesis::metadata`.
+    pub fn metadata(self, metadata: Metadata) -> Self {
+        metadata::declare(self.name, metadata);
+        self
+    }
+
+    /// Add a label to every metric reported for this run, for example to break
+    /// mismatches down by tenant or region. Setting a label again replaces its
+    /// value. Only the first 100 distinct values of each label are reported per
+    /// experiment, later values are reported as `__overflow__`. Observers other
+    /// than the default observer may ignore labels, see
+    /// `ExperimentObserver::with_labels`.
+    ///
+    /// `key` can't be one of the labels thesis already reports: `name`,
+    /// `kind`, `outcome`, `reason` or `category`. Such labels panic in debug
+    /// builds, and are ignored in release builds.
+    pub fn label(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        let reserved = RESERVED_LABELS.contains(&key);
+        debug_assert!(!reserved, "the {} label is reserved by thesis", key);
+        if reserved {
+            return self;
+        }
+
+        let value = value.into();
+        match self.labels.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.labels.push((key, value)),
+        }
+        self
+    }
+
+    /// Allow at most `limit` experimental futures of experiments with this
+    /// name to be in flight at once. When the rollout strategy returns
+    /// `RolloutDecision::UseExperimentalAndCompare` while the limit is
+    /// reached, only the control is run instead.
+    pub fn experimental_concurrency_limit(self, limit: usize) -> Self {
+        Experiment {
+            experimental_concurrency_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Classify mismatches with the given function. The category it returns is
+    /// passed to the observer, which by default adds it as the `category`
+    /// label of `thesis_experiment_outcome` and to the mismatch log. Only the
+    /// first 16 distinct categories of each experiment are reported, and any
+    /// others are reported as `other`.
+    pub fn classify_mismatch<F>(self, classifier: F) -> Self
+    where
+        F: Fn(&Mismatch<T>) -> &'static str + Send + Sync + 'static,
+    {
+        Experiment {
+            mismatch_classifier: Some(Box::new(classifier)),
+            ..self
+        }
+    }
+
+    /// Downgrade `UseExperimentalAndCompare` to `UseControl` if the
+    /// experimental concurrency limit has been reached. The returned permit
+    /// must be held until the experimental future finishes.
+    fn limit_concurrency(
+        &self,
+        observer: &dyn ExperimentObserver,
+        decision: RolloutDecision,
+    ) -> (RolloutDecision, Option<concurrency::Permit>) {
+        match (decision, self.experimental_concurrency_limit) {
+            (RolloutDecision::UseExperimentalAndCompare, Some(limit)) => {
+                match concurrency::try_acquire(self.name, limit) {
+                    Some(permit) => (decision, Some(permit)),
+                    None => {
+                        observer.on_skipped(self.name, "concurrency");
+                        (RolloutDecision::UseControl, None)
+                    }
+                }
+            }
+            _ => (decision, None),
         }
     }
 
@@ -152,209 +636,937 @@ impl<T, C, E, R, M> Experiment<T, C, E, R, M> {
         C: Future<Output = T>,
         E: Future<Output = T>,
     {
-        let span = info_span!("Experiment::run", experiment_name = self.name);
-        counter!("thesis_experiment_run_total", "name" => self.name).increment(1);
+        self.run_with_report().await.0
+    }
 
-        async move {
-            match self.rollout_strategy.rollout_decision() {
-                RolloutDecision::UseControl => {
-                    counter!(
-                        "thesis_experiment_run_variant",
-                        "name" => self.name,
-                        "kind" => "control",
-                    )
-                    .increment(1);
+    /// Run the experiment with the parameters provided, where the control and
+    /// experimental were given as closures with `control_fn` and
+    /// `experimental_fn`. When both run, the control runs first.
+    pub fn run_sync(self) -> T
+    where
+        T: PartialEq,
+        R: RolloutStrategy,
+        M: MismatchHandler<T>,
+        C: FnOnce() -> T,
+        E: FnOnce() -> T,
+    {
+        self.into_lazy()
+            .run()
+            .now_or_never()
+            .expect("synchronous experiments complete in a single poll")
+    }
 
-                    instrument_control(self.name, self.control_builder).await
+    /// Wrap the control and experimental closures in futures which are ready
+    /// as soon as they are polled
+    fn into_lazy(self) -> Experiment<T, impl Future<Output = T>, impl Future<Output = T>, R, M>
+    where
+        C: FnOnce() -> T,
+        E: FnOnce() -> T,
+    {
+        let control = self.control_builder;
+        let experimental = self.experimental_builder;
+
+        Experiment {
+            control_builder: future::lazy(|_| control()),
+            experimental_builder: future::lazy(|_| experimental()),
+            name: self.name,
+            result_type: self.result_type,
+            rollout_strategy: self.rollout_strategy,
+            mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+            experimental_concurrency_limit: self.experimental_concurrency_limit,
+            mismatch_classifier: self.mismatch_classifier,
+            error_classifier: self.error_classifier,
+            labels: self.labels,
+        }
+    }
+
+    /// Run the experiment with the parameters provided, where the control and
+    /// experimental were given as closures with `control_fn` and
+    /// `experimental_fn`. Each closure runs on a blocking thread spawned with
+    /// `runtime::global_spawner`, so when both run they run in parallel, and
+    /// the duration of one doesn't include the time spent running the other.
+    /// If no global spawner is available, each closure runs on a new thread
+    /// instead.
+    pub async fn run_blocking(self) -> T
+    where
+        T: PartialEq + Send + 'static,
+        R: RolloutStrategy,
+        M: MismatchHandler<T>,
+        C: FnOnce() -> T + Send + 'static,
+        E: FnOnce() -> T + Send + 'static,
+    {
+        self.into_blocking(runtime::global_spawner()).run().await
+    }
+
+    /// Wrap the control and experimental closures in futures which run them on
+    /// blocking threads once they are polled
+    fn into_blocking(
+        self,
+        spawner: Option<Arc<dyn Spawner>>,
+    ) -> Experiment<T, impl Future<Output = T>, impl Future<Output = T>, R, M>
+    where
+        T: Send + 'static,
+        C: FnOnce() -> T + Send + 'static,
+        E: FnOnce() -> T + Send + 'static,
+    {
+        Experiment {
+            control_builder: spawn_blocking(spawner.clone(), self.control_builder),
+            experimental_builder: spawn_blocking(spawner, self.experimental_builder),
+            name: self.name,
+            result_type: self.result_type,
+            rollout_strategy: self.rollout_strategy,
+            mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+            experimental_concurrency_limit: self.experimental_concurrency_limit,
+            mismatch_classifier: self.mismatch_classifier,
+            error_classifier: self.error_classifier,
+            labels: self.labels,
+        }
+    }
+
+    /// Run the experiment with the parameters provided, returning a `Report`
+    /// describing the run alongside the value
+    pub async fn run_with_report(mut self) -> (T, Report)
+    where
+        T: PartialEq,
+        R: RolloutStrategy,
+        M: MismatchHandler<T>,
+        C: Future<Output = T>,
+        E: Future<Output = T>,
+    {
+        let observer = observer::for_run(self.name, self.observer.take(), &self.labels);
+        let classifier = self.mismatch_classifier.take();
+        let metadata = metadata::for_run(self.name);
+        let span = info_span!(
+            "Experiment::run",
+            experiment_name = self.name,
+            owner = metadata.owner,
+            description = metadata.description,
+            ticket = metadata.ticket,
+            expires = metadata.expires_unix_secs(),
+        );
+
+        #[cfg(not(feature = "tracing"))]
+        let _ = metadata;
+        observer.on_run_start(self.name);
+        let scope = observer.run_scope(self.name);
+
+        let run = async move {
+            let decision = rollout::decide(self.name, &self.rollout_strategy);
+            let (decision, _permit) = self.limit_concurrency(&observer, decision);
+            observer.on_decision(self.name, decision);
+
+            let (value, report) = match decision {
+                RolloutDecision::UseControl => {
+                    run_control_only(&observer, self.name, decision, self.control_builder).await
                 }
                 RolloutDecision::UseExperimentalAndCompare => {
-                    counter!(
-                        "thesis_experiment_run_variant",
-                        "name" => self.name,
-                        "kind" => "experimental_and_compare",
-                    )
-                    .increment(1);
+                    let ((control, control_duration), (experimental, experimental_duration)) = futures::join!(
+                        instrument_control(&observer, self.name, self.control_builder),
+                        instrument_experimental(&observer, self.name, self.experimental_builder),
+                    );
 
-                    let (control, experimental) = tokio::join!(
-                        instrument_control(self.name, self.control_builder),
-                        instrument_experimental(self.name, self.experimental_builder),
+                    record_latency(
+                        &observer,
+                        self.name,
+                        control_duration,
+                        experimental_duration,
                     );
 
-                    if control != experimental {
-                        outcome_mismatch(self.name);
+                    let mut report = Report {
+                        decision,
+                        control_duration: Some(control_duration),
+                        experimental_duration: Some(experimental_duration),
+                        outcome: Outcome::Match,
+                        returned: Returned::Control,
+                    };
 
+                    if control != experimental {
                         let mismatch = Mismatch {
                             control,
                             experimental,
                         };
+                        report_mismatch(&observer, self.name, classifier.as_ref(), &mismatch);
+
+                        report.outcome = Outcome::Mismatch;
+                        report.returned = Returned::MismatchHandler;
 
-                        return self.mismatch_handler.on_mismatch(mismatch);
+                        (self.mismatch_handler.on_mismatch(mismatch), report)
+                    } else {
+                        (control, report)
                     }
+                }
+                RolloutDecision::UseExperimental => {
+                    run_experimental_only(&observer, self.name, decision, self.experimental_builder)
+                        .await
+                }
+                RolloutDecision::UseExperimentalWithFallback => {
+                    run_with_fallback(
+                        &observer,
+                        self.name,
+                        decision,
+                        self.control_builder,
+                        self.experimental_builder,
+                    )
+                    .await
+                }
+            };
+
+            observer.on_run_end(self.name, &report);
+
+            (value, report)
+        };
+
+        observer::scoped(scope, run.instrument(span)).await
+    }
 
-                    control
+    /// Run the experiment with the parameters provided, racing the control
+    /// and experimental against each other when the rollout strategy returns
+    /// `RolloutDecision::UseExperimentalAndCompare`. The value of whichever
+    /// finishes first is returned straight away, while the slower one keeps
+    /// running on a task spawned with `runtime::global_spawner` so the two
+    /// values can still be compared. The mismatch handler is told about
+    /// mismatches, but the value it returns is ignored.
+    ///
+    /// If no global spawner is available, the methods are still raced, but
+    /// the slower one is waited for and the values compared before the
+    /// winner's value is returned.
+    pub async fn run_race(self) -> T
+    where
+        T: PartialEq + Clone + Send + 'static,
+        R: RolloutStrategy,
+        M: MismatchHandler<T> + Send + 'static,
+        C: Future<Output = T> + Send + 'static,
+        E: Future<Output = T> + Send + 'static,
+    {
+        self.race_on(runtime::global_spawner()).await
+    }
+
+    /// Run a race, comparing the values on a task spawned with `spawner`, or
+    /// before returning if there is none
+    pub(crate) async fn race_on(mut self, spawner: Option<Arc<dyn Spawner>>) -> T
+    where
+        T: PartialEq + Clone + Send + 'static,
+        R: RolloutStrategy,
+        M: MismatchHandler<T> + Send + 'static,
+        C: Future<Output = T> + Send + 'static,
+        E: Future<Output = T> + Send + 'static,
+    {
+        let observer = observer::for_run(self.name, self.observer.take(), &self.labels);
+        let classifier = self.mismatch_classifier.take();
+        let metadata = metadata::for_run(self.name);
+        let span = info_span!(
+            "Experiment::run",
+            experiment_name = self.name,
+            owner = metadata.owner,
+            description = metadata.description,
+            ticket = metadata.ticket,
+            expires = metadata.expires_unix_secs(),
+        );
+
+        #[cfg(not(feature = "tracing"))]
+        let _ = metadata;
+        let comparison_span = span.clone();
+        observer.on_run_start(self.name);
+        let scope = observer.run_scope(self.name);
+        let comparison_scope = scope.clone();
+
+        let run = async move {
+            let decision = rollout::decide(self.name, &self.rollout_strategy);
+            let (decision, permit) = self.limit_concurrency(&observer, decision);
+            observer.on_decision(self.name, decision);
+
+            let (value, report) = match decision {
+                RolloutDecision::UseControl => {
+                    run_control_only(&observer, self.name, decision, self.control_builder).await
                 }
                 RolloutDecision::UseExperimental => {
-                    counter!(
-                        "thesis_experiment_run_variant",
-                        "name" => self.name,
-                        "kind" => "experimental",
+                    run_experimental_only(&observer, self.name, decision, self.experimental_builder)
+                        .await
+                }
+                RolloutDecision::UseExperimentalWithFallback => {
+                    run_with_fallback(
+                        &observer,
+                        self.name,
+                        decision,
+                        self.control_builder,
+                        self.experimental_builder,
+                    )
+                    .await
+                }
+                RolloutDecision::UseExperimentalAndCompare => {
+                    let name = self.name;
+                    let mismatch_handler = self.mismatch_handler;
+                    let race = race(
+                        &observer,
+                        name,
+                        self.control_builder,
+                        self.experimental_builder,
                     )
-                    .increment(1);
+                    .await;
+                    observer.on_race_won(name, race.winner_kind());
 
-                    instrument_experimental(self.name, self.experimental_builder).await
+                    let value = race.output.clone();
+                    let returned = race.winner;
+
+                    let comparison = async move {
+                        let ((control, control_duration), (experimental, experimental_duration)) =
+                            race.finish().await;
+                        drop(permit);
+
+                        record_latency(&observer, name, control_duration, experimental_duration);
+
+                        let mut report = Report {
+                            decision,
+                            control_duration: Some(control_duration),
+                            experimental_duration: Some(experimental_duration),
+                            outcome: Outcome::Match,
+                            returned,
+                        };
+
+                        if control != experimental {
+                            let mismatch = Mismatch {
+                                control,
+                                experimental,
+                            };
+                            report_mismatch(&observer, name, classifier.as_ref(), &mismatch);
+                            report.outcome = Outcome::Mismatch;
+
+                            mismatch_handler.on_mismatch(mismatch);
+                        }
+
+                        observer.on_run_end(name, &report);
+                    };
+                    match spawner {
+                        Some(spawner) => {
+                            let comparison = comparison.instrument(comparison_span);
+                            spawner.spawn(observer::scoped(comparison_scope, comparison).boxed());
+                        }
+                        // Without a spawner, the values are compared before the
+                        // winner's value is returned
+                        None => comparison.await,
+                    }
+
+                    return value;
                 }
-            }
-        }
-        .instrument(span)
-        .await
+            };
+
+            observer.on_run_end(self.name, &report);
+
+            value
+        };
+
+        observer::scoped(scope, run.instrument(span)).await
     }
 }
 
-fn outcome_error<E>(name: &'static str, kind: &'static str, error: &E)
-where
+fn outcome<T, E>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    kind: &'static str,
+    classifier: Option<&OutputClassifier<Result<T, E>>>,
+    result: &Result<T, E>,
+) where
     E: Display,
 {
-    counter!(
-        "thesis_experiment_outcome",
-        "name" => name,
-        "kind" => kind,
-        "outcome" => "error",
-    )
-    .increment(1);
+    match result {
+        Ok(_) => {
+            observer.on_outcome(name, kind, BranchOutcome::Ok);
+        }
+        Err(error) => {
+            let category = classifier
+                .and_then(|classify| classify(result))
+                .map(|category| cardinality::error_category(name, category));
 
-    tracing::error!(name, kind, %error, "thesis experiment error");
+            observer.on_outcome(name, kind, BranchOutcome::Error { error, category });
+        }
+    }
 }
 
-fn outcome_ok(name: &'static str, kind: &'static str) {
-    counter!(
-        "thesis_experiment_outcome",
-        "name" => name,
-        "kind" => kind,
-        "outcome" => "ok",
-    )
-    .increment(1);
+/// The outcome of a `run_result` run where only one method ran
+fn single_branch_outcome<T, E>(result: &Result<T, E>) -> Outcome {
+    match result {
+        Ok(_) => Outcome::Ignored,
+        Err(_) => Outcome::Error,
+    }
 }
 
-fn outcome_mismatch(name: &'static str) {
-    counter!(
-        "thesis_experiment_outcome",
-        "name" => name,
-        "kind" => "experimental_and_compare",
-        "outcome" => "mismatch",
-    )
-    .increment(1);
+/// Record the durations of a run where both methods ran
+fn record_latency(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    control: Duration,
+    experimental: Duration,
+) {
+    latency::record(name, control, experimental);
+    observer.on_latency_delta(name, control, experimental);
 }
 
-fn outcome<T, E>(name: &'static str, kind: &'static str, result: &Result<T, E>)
-where
-    E: Display,
-{
-    match result {
-        Ok(_) => {
-            outcome_ok(name, kind);
-        }
-        Err(e) => {
-            outcome_error(name, kind, e);
+/// Tell the observer about a mismatch, along with its category if the
+/// experiment classifies mismatches
+fn report_mismatch<T>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    classifier: Option<&MismatchClassifier<T>>,
+    mismatch: &Mismatch<T>,
+) {
+    let category =
+        classifier.map(|classify| cardinality::mismatch_category(name, classify(mismatch)));
+    observer.on_mismatch(name, category);
+}
+
+impl<T, Err, C, E, R, M> Experiment<Result<T, Err>, C, E, R, M> {
+    /// Classify the errors returned by the control and experimental with the
+    /// given classifier. The category it returns is passed to the observer,
+    /// which by default adds it as the `category` label of
+    /// `thesis_experiment_outcome`. Like mismatch categories, only the first 16
+    /// distinct categories of each experiment are reported, and any others are
+    /// reported as `other`.
+    pub fn classify_error<EC>(self, classifier: EC) -> Self
+    where
+        EC: ErrorClassifier<Err> + Send + Sync + 'static,
+    {
+        Experiment {
+            error_classifier: Some(Box::new(move |result: &Result<T, Err>| {
+                result
+                    .as_ref()
+                    .err()
+                    .map(|error| classifier.classify(error))
+            })),
+            ..self
         }
     }
-}
 
-impl<T, Err, C, E, R, M> Experiment<Result<T, Err>, C, E, R, M> {
-    /// Run the experiment with the parameters provided
-    pub async fn run_result(self) -> Result<T, Err>
+    /// Run the experiment with the parameters provided
+    pub async fn run_result(self) -> Result<T, Err>
+    where
+        T: PartialEq,
+        R: RolloutStrategy,
+        M: MismatchHandler<Result<T, Err>>,
+        C: Future<Output = Result<T, Err>>,
+        E: Future<Output = Result<T, Err>>,
+        Err: Display,
+    {
+        self.run_result_with_report().await.0
+    }
+
+    /// Run the experiment with the parameters provided, where the control and
+    /// experimental were given as closures with `control_fn` and
+    /// `experimental_fn`. When both run, the control runs first.
+    pub fn run_result_sync(self) -> Result<T, Err>
+    where
+        T: PartialEq,
+        R: RolloutStrategy,
+        M: MismatchHandler<Result<T, Err>>,
+        C: FnOnce() -> Result<T, Err>,
+        E: FnOnce() -> Result<T, Err>,
+        Err: Display,
+    {
+        self.into_lazy()
+            .run_result()
+            .now_or_never()
+            .expect("synchronous experiments complete in a single poll")
+    }
+
+    /// Run the experiment with the parameters provided, where the control and
+    /// experimental were given as closures with `control_fn` and
+    /// `experimental_fn`. Like `run_blocking`, each closure runs on a blocking
+    /// thread spawned with `runtime::global_spawner`.
+    pub async fn run_result_blocking(self) -> Result<T, Err>
+    where
+        T: PartialEq + Send + 'static,
+        R: RolloutStrategy,
+        M: MismatchHandler<Result<T, Err>>,
+        C: FnOnce() -> Result<T, Err> + Send + 'static,
+        E: FnOnce() -> Result<T, Err> + Send + 'static,
+        Err: Display + Send + 'static,
+    {
+        self.into_blocking(runtime::global_spawner())
+            .run_result()
+            .await
+    }
+
+    /// Run the experiment with the parameters provided, returning a `Report`
+    /// describing the run alongside the result
+    pub async fn run_result_with_report(mut self) -> (Result<T, Err>, Report)
+    where
+        T: PartialEq,
+        R: RolloutStrategy,
+        M: MismatchHandler<Result<T, Err>>,
+        C: Future<Output = Result<T, Err>>,
+        E: Future<Output = Result<T, Err>>,
+        Err: Display,
+    {
+        let observer = observer::for_run(self.name, self.observer.take(), &self.labels);
+        let classifier = self.mismatch_classifier.take();
+        let error_classifier = self.error_classifier.take();
+        let metadata = metadata::for_run(self.name);
+        let span = info_span!(
+            "Experiment::run",
+            experiment_name = self.name,
+            owner = metadata.owner,
+            description = metadata.description,
+            ticket = metadata.ticket,
+            expires = metadata.expires_unix_secs(),
+        );
+
+        #[cfg(not(feature = "tracing"))]
+        let _ = metadata;
+        observer.on_run_start(self.name);
+        let scope = observer.run_scope(self.name);
+
+        let run = async move {
+            let decision = rollout::decide(self.name, &self.rollout_strategy);
+            let (decision, _permit) = self.limit_concurrency(&observer, decision);
+            observer.on_decision(self.name, decision);
+
+            let (result, report) = match decision {
+                RolloutDecision::UseControl => {
+                    let (result, mut report) =
+                        run_control_only(&observer, self.name, decision, self.control_builder)
+                            .await;
+                    outcome(
+                        &observer,
+                        self.name,
+                        "control",
+                        error_classifier.as_ref(),
+                        &result,
+                    );
+                    report.outcome = single_branch_outcome(&result);
+
+                    (result, report)
+                }
+                RolloutDecision::UseExperimentalAndCompare => {
+                    let ((control, control_duration), (experimental, experimental_duration)) = futures::join!(
+                        instrument_control(&observer, self.name, self.control_builder),
+                        instrument_experimental(&observer, self.name, self.experimental_builder)
+                    );
+
+                    outcome(
+                        &observer,
+                        self.name,
+                        "control",
+                        error_classifier.as_ref(),
+                        &control,
+                    );
+                    outcome(
+                        &observer,
+                        self.name,
+                        "experimental",
+                        error_classifier.as_ref(),
+                        &experimental,
+                    );
+
+                    record_latency(
+                        &observer,
+                        self.name,
+                        control_duration,
+                        experimental_duration,
+                    );
+
+                    let mut report = Report {
+                        decision,
+                        control_duration: Some(control_duration),
+                        experimental_duration: Some(experimental_duration),
+                        outcome: Outcome::Match,
+                        returned: Returned::Control,
+                    };
+
+                    let result = match (control, experimental) {
+                        (Ok(control), Ok(experimental)) => {
+                            if control != experimental {
+                                let mismatch = Mismatch {
+                                    control: Ok(control),
+                                    experimental: Ok(experimental),
+                                };
+                                report_mismatch(
+                                    &observer,
+                                    self.name,
+                                    classifier.as_ref(),
+                                    &mismatch,
+                                );
+
+                                report.outcome = Outcome::Mismatch;
+                                report.returned = Returned::MismatchHandler;
+
+                                self.mismatch_handler.on_mismatch(mismatch)
+                            } else {
+                                Ok(control)
+                            }
+                        }
+                        (Ok(control), Err(experimental)) => {
+                            let mismatch = Mismatch {
+                                control: Ok(control),
+                                experimental: Err(experimental),
+                            };
+                            report_mismatch(&observer, self.name, classifier.as_ref(), &mismatch);
+                            report.outcome = Outcome::Mismatch;
+
+                            mismatch.control
+                        }
+                        (Err(control), Ok(experimental)) => {
+                            let mismatch = Mismatch {
+                                control: Err(control),
+                                experimental: Ok(experimental),
+                            };
+                            report_mismatch(&observer, self.name, classifier.as_ref(), &mismatch);
+
+                            report.outcome = Outcome::Mismatch;
+                            report.returned = Returned::MismatchHandler;
+
+                            self.mismatch_handler.on_mismatch(mismatch)
+                        }
+                        (Err(control), Err(_)) => {
+                            report.outcome = Outcome::Error;
+
+                            Err(control)
+                        }
+                    };
+
+                    (result, report)
+                }
+                RolloutDecision::UseExperimental => {
+                    let (result, mut report) = run_experimental_only(
+                        &observer,
+                        self.name,
+                        decision,
+                        self.experimental_builder,
+                    )
+                    .await;
+                    outcome(
+                        &observer,
+                        self.name,
+                        "experimental",
+                        error_classifier.as_ref(),
+                        &result,
+                    );
+                    report.outcome = single_branch_outcome(&result);
+
+                    (result, report)
+                }
+                RolloutDecision::UseExperimentalWithFallback => {
+                    run_result_with_fallback(
+                        &observer,
+                        self.name,
+                        error_classifier.as_ref(),
+                        decision,
+                        self.control_builder,
+                        self.experimental_builder,
+                    )
+                    .await
+                }
+            };
+
+            observer.on_run_end(self.name, &report);
+
+            (result, report)
+        };
+
+        observer::scoped(scope, run.instrument(span)).await
+    }
+
+    /// Run the experiment with the parameters provided, racing the control
+    /// and experimental against each other when the rollout strategy returns
+    /// `RolloutDecision::UseExperimentalAndCompare`. The first `Ok` result is
+    /// returned straight away, while the slower method keeps running on a
+    /// task spawned with `runtime::global_spawner` so the two results can
+    /// still be compared. If the first method to finish returns `Err`, the
+    /// other one is waited for, and the control's error is returned if both
+    /// fail. The mismatch handler is told about mismatches in the same cases
+    /// as with `run_result`, but the value it returns is ignored.
+    ///
+    /// If no global spawner is available, the methods are still raced, but
+    /// the slower one is waited for and the results compared before the
+    /// winner's result is returned.
+    pub async fn run_result_race(self) -> Result<T, Err>
+    where
+        T: PartialEq + Clone + Send + 'static,
+        R: RolloutStrategy,
+        M: MismatchHandler<Result<T, Err>> + Send + 'static,
+        C: Future<Output = Result<T, Err>> + Send + 'static,
+        E: Future<Output = Result<T, Err>> + Send + 'static,
+        Err: Display + Send + 'static,
+    {
+        self.result_race_on(runtime::global_spawner()).await
+    }
+
+    /// Run a race of `Result`s, comparing them on a task spawned with
+    /// `spawner`, or before returning if there is none
+    async fn result_race_on(mut self, spawner: Option<Arc<dyn Spawner>>) -> Result<T, Err>
     where
-        T: PartialEq,
+        T: PartialEq + Clone + Send + 'static,
         R: RolloutStrategy,
-        M: MismatchHandler<Result<T, Err>>,
-        C: Future<Output = Result<T, Err>>,
-        E: Future<Output = Result<T, Err>>,
-        Err: Display,
+        M: MismatchHandler<Result<T, Err>> + Send + 'static,
+        C: Future<Output = Result<T, Err>> + Send + 'static,
+        E: Future<Output = Result<T, Err>> + Send + 'static,
+        Err: Display + Send + 'static,
     {
-        let span = info_span!("Experiment::run", experiment_name = self.name);
-        counter!("thesis_experiment_run_total", "name" => self.name).increment(1);
+        let observer = observer::for_run(self.name, self.observer.take(), &self.labels);
+        let classifier = self.mismatch_classifier.take();
+        let error_classifier = self.error_classifier.take();
+        let metadata = metadata::for_run(self.name);
+        let span = info_span!(
+            "Experiment::run",
+            experiment_name = self.name,
+            owner = metadata.owner,
+            description = metadata.description,
+            ticket = metadata.ticket,
+            expires = metadata.expires_unix_secs(),
+        );
+
+        #[cfg(not(feature = "tracing"))]
+        let _ = metadata;
+        let comparison_span = span.clone();
+        observer.on_run_start(self.name);
+        let scope = observer.run_scope(self.name);
+        let comparison_scope = scope.clone();
+
+        let run = async move {
+            let decision = rollout::decide(self.name, &self.rollout_strategy);
+            let (decision, permit) = self.limit_concurrency(&observer, decision);
+            observer.on_decision(self.name, decision);
 
-        async move {
-            match self.rollout_strategy.rollout_decision() {
+            let (result, report) = match decision {
                 RolloutDecision::UseControl => {
-                    counter!(
-                        "thesis_experiment_run_variant",
-                        "name" => self.name,
-                        "kind" => "control",
-                    )
-                    .increment(1);
+                    let (result, mut report) =
+                        run_control_only(&observer, self.name, decision, self.control_builder)
+                            .await;
+                    outcome(
+                        &observer,
+                        self.name,
+                        "control",
+                        error_classifier.as_ref(),
+                        &result,
+                    );
+                    report.outcome = single_branch_outcome(&result);
 
-                    let result = instrument_control(self.name, self.control_builder).await;
-                    outcome(self.name, "control", &result);
+                    (result, report)
+                }
+                RolloutDecision::UseExperimental => {
+                    let (result, mut report) = run_experimental_only(
+                        &observer,
+                        self.name,
+                        decision,
+                        self.experimental_builder,
+                    )
+                    .await;
+                    outcome(
+                        &observer,
+                        self.name,
+                        "experimental",
+                        error_classifier.as_ref(),
+                        &result,
+                    );
+                    report.outcome = single_branch_outcome(&result);
 
-                    result
+                    (result, report)
+                }
+                RolloutDecision::UseExperimentalWithFallback => {
+                    run_result_with_fallback(
+                        &observer,
+                        self.name,
+                        error_classifier.as_ref(),
+                        decision,
+                        self.control_builder,
+                        self.experimental_builder,
+                    )
+                    .await
                 }
                 RolloutDecision::UseExperimentalAndCompare => {
-                    counter!(
-                        "thesis_experiment_run_variant",
-                        "name" => self.name,
-                        "kind" => "experimental_and_compare",
+                    let name = self.name;
+                    let mismatch_handler = self.mismatch_handler;
+                    let race = race(
+                        &observer,
+                        name,
+                        self.control_builder,
+                        self.experimental_builder,
                     )
-                    .increment(1);
-
-                    let (control, experimental) = tokio::join!(
-                        instrument_control(self.name, self.control_builder),
-                        instrument_experimental(self.name, self.experimental_builder)
+                    .await;
+                    outcome(
+                        &observer,
+                        name,
+                        race.winner_kind(),
+                        error_classifier.as_ref(),
+                        &race.output,
                     );
+                    let loser_kind = race.loser_kind();
 
-                    outcome(self.name, "control", &control);
-                    outcome(self.name, "experimental", &experimental);
+                    if let Ok(value) = &race.output {
+                        observer.on_race_won(name, race.winner_kind());
 
-                    match (control, experimental) {
-                        (Ok(control), Ok(experimental)) => {
-                            if control != experimental {
-                                outcome_mismatch(self.name);
+                        let value = value.clone();
+                        let returned = race.winner;
 
-                                let mismatch = Mismatch {
-                                    control: Ok(control),
-                                    experimental: Ok(experimental),
-                                };
+                        let comparison = async move {
+                            let (
+                                (control, control_duration),
+                                (experimental, experimental_duration),
+                            ) = race.finish().await;
+                            drop(permit);
 
-                                return self.mismatch_handler.on_mismatch(mismatch);
-                            }
+                            let loser = match returned {
+                                Returned::Control => &experimental,
+                                _ => &control,
+                            };
+                            outcome(
+                                &observer,
+                                name,
+                                loser_kind,
+                                error_classifier.as_ref(),
+                                loser,
+                            );
+
+                            record_latency(
+                                &observer,
+                                name,
+                                control_duration,
+                                experimental_duration,
+                            );
+
+                            let report = Report {
+                                decision,
+                                control_duration: Some(control_duration),
+                                experimental_duration: Some(experimental_duration),
+                                outcome: compare_race_results(
+                                    &observer,
+                                    name,
+                                    classifier.as_ref(),
+                                    mismatch_handler,
+                                    control,
+                                    experimental,
+                                ),
+                                returned,
+                            };
 
-                            Ok(control)
+                            observer.on_run_end(name, &report);
+                        };
+                        match spawner {
+                            Some(spawner) => {
+                                let comparison = comparison.instrument(comparison_span);
+                                spawner
+                                    .spawn(observer::scoped(comparison_scope, comparison).boxed());
+                            }
+                            None => comparison.await,
                         }
-                        (Ok(control), Err(_)) => {
-                            outcome_mismatch(self.name);
 
-                            Ok(control)
-                        }
-                        (Err(control), Ok(experimental)) => {
-                            outcome_mismatch(self.name);
+                        return Ok(value);
+                    }
 
-                            let mismatch = Mismatch {
-                                control: Err(control),
-                                experimental: Ok(experimental),
-                            };
+                    // The first method to finish failed, so the race is won by
+                    // the other one if it succeeds
+                    let winner = race.winner;
+                    let ((control, control_duration), (experimental, experimental_duration)) =
+                        race.finish().await;
+
+                    let loser = match winner {
+                        Returned::Control => &experimental,
+                        _ => &control,
+                    };
+                    outcome(
+                        &observer,
+                        name,
+                        loser_kind,
+                        error_classifier.as_ref(),
+                        loser,
+                    );
 
-                            self.mismatch_handler.on_mismatch(mismatch)
+                    record_latency(&observer, name, control_duration, experimental_duration);
+
+                    let mut report = Report {
+                        decision,
+                        control_duration: Some(control_duration),
+                        experimental_duration: Some(experimental_duration),
+                        outcome: Outcome::Error,
+                        returned: Returned::Control,
+                    };
+
+                    let success = loser.as_ref().ok().map(|value| (value.clone(), loser_kind));
+
+                    let result = match success {
+                        Some((value, kind)) => {
+                            observer.on_race_won(name, kind);
+
+                            report.returned = match winner {
+                                Returned::Control => Returned::Experimental,
+                                _ => Returned::Control,
+                            };
+                            report.outcome = compare_race_results(
+                                &observer,
+                                name,
+                                classifier.as_ref(),
+                                mismatch_handler,
+                                control,
+                                experimental,
+                            );
+
+                            Ok(value)
                         }
-                        (Err(control), Err(_)) => Err(control),
-                    }
+                        None => control,
+                    };
+
+                    (result, report)
                 }
-                RolloutDecision::UseExperimental => {
-                    counter!(
-                        "thesis_experiment_run_variant",
-                        "name" => self.name,
-                        "kind" => "experimental",
-                    )
-                    .increment(1);
+            };
 
-                    let result =
-                        instrument_experimental(self.name, self.experimental_builder).await;
-                    outcome(self.name, "experimental", &result);
+            observer.on_run_end(self.name, &report);
 
-                    result
-                }
+            result
+        };
+
+        observer::scoped(scope, run.instrument(span)).await
+    }
+}
+
+/// Compare the results of both methods of a race, telling the observer and
+/// mismatch handler about mismatches in the same cases as `run_result`
+fn compare_race_results<T, Err, M>(
+    observer: &dyn ExperimentObserver,
+    name: &'static str,
+    classifier: Option<&MismatchClassifier<Result<T, Err>>>,
+    mismatch_handler: M,
+    control: Result<T, Err>,
+    experimental: Result<T, Err>,
+) -> Outcome
+where
+    T: PartialEq,
+    M: MismatchHandler<Result<T, Err>>,
+{
+    match (control, experimental) {
+        (Ok(control), Ok(experimental)) => {
+            if control == experimental {
+                return Outcome::Match;
             }
+
+            let mismatch = Mismatch {
+                control: Ok(control),
+                experimental: Ok(experimental),
+            };
+            report_mismatch(observer, name, classifier, &mismatch);
+            let _ = mismatch_handler.on_mismatch(mismatch);
+
+            Outcome::Mismatch
         }
-        .instrument(span)
-        .await
+        (Ok(control), Err(experimental)) => {
+            let mismatch = Mismatch {
+                control: Ok(control),
+                experimental: Err(experimental),
+            };
+            report_mismatch(observer, name, classifier, &mismatch);
+
+            Outcome::Mismatch
+        }
+        (Err(control), Ok(experimental)) => {
+            let mismatch = Mismatch {
+                control: Err(control),
+                experimental: Ok(experimental),
+            };
+            report_mismatch(observer, name, classifier, &mismatch);
+            let _ = mismatch_handler.on_mismatch(mismatch);
+
+            Outcome::Mismatch
+        }
+        (Err(_), Err(_)) => Outcome::Error,
     }
 }
 
@@ -362,6 +1574,7 @@ impl<T, Err, C, E, R, M> Experiment<Result<T, Err>, C, E, R, M> {
 mod tests {
     use super::*;
     use crate::rollout::Percent;
+    use crate::testing::{Event, RecordingObserver};
 
     #[tokio::test]
     async fn it_resolves_conflict_with_mismatch() {
@@ -388,26 +1601,30 @@ mod tests {
 
     #[tokio::test]
     async fn it_rolls_out_correctly() {
-        let mut trues = 0;
-        let mut falses = 0;
-
-        for _ in 0..10_000usize {
-            let exists = Experiment::new("test")
-                .control(async { true })
-                .experimental(async { false })
-                .rollout_strategy(Percent::new(5.0))
-                .on_mismatch(|mismatch| mismatch.experimental)
-                .run()
-                .await;
-
-            if exists {
-                trues += 1;
-            } else {
-                falses += 1;
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha8Rng;
+
+        async fn roll_out(percent: Percent) -> Vec<bool> {
+            let mut results = Vec::new();
+            for _ in 0..10_000usize {
+                let exists = Experiment::new("test")
+                    .control(async { true })
+                    .experimental(async { false })
+                    .rollout_strategy(&percent)
+                    .on_mismatch(|mismatch| mismatch.experimental)
+                    .run()
+                    .await;
+                results.push(exists);
             }
+            results
         }
 
-        let experimental_rate = falses as f64 / (trues + falses) as f64;
+        let seeded = || Percent::new(5.0).with_rng(ChaCha8Rng::seed_from_u64(5));
+        let results = roll_out(seeded()).await;
+        assert_eq!(results, roll_out(seeded()).await);
+
+        let falses = results.iter().filter(|exists| !**exists).count();
+        let experimental_rate = falses as f64 / results.len() as f64;
 
         // Actual rate will be calculated via RNG, should be .04, .05, or .06.
         assert!(
@@ -541,4 +1758,612 @@ mod tests {
         assert!(exists);
         assert!(!seen);
     }
+
+    #[tokio::test]
+    async fn it_reports_mismatches() {
+        let (value, report) = Experiment::new("test")
+            .control(async { 1 })
+            .experimental(async { 2 })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|mismatch| mismatch.experimental)
+            .run_with_report()
+            .await;
+
+        assert_eq!(value, 2);
+        assert_eq!(report.decision, RolloutDecision::UseExperimentalAndCompare);
+        assert_eq!(report.outcome, Outcome::Mismatch);
+        assert_eq!(report.returned, Returned::MismatchHandler);
+        assert!(report.control_duration.is_some());
+        assert!(report.experimental_duration.is_some());
+    }
+
+    #[tokio::test]
+    async fn it_reports_ignored_runs() {
+        let (value, report) = Experiment::new("test")
+            .control(async { Ok::<_, &str>(1) })
+            .experimental(async { Ok::<_, &str>(2) })
+            .rollout_strategy(RolloutDecision::UseControl)
+            .run_result_with_report()
+            .await;
+
+        assert_eq!(value, Ok(1));
+        assert_eq!(report.outcome, Outcome::Ignored);
+        assert_eq!(report.returned, Returned::Control);
+        assert!(report.control_duration.is_some());
+        assert!(report.experimental_duration.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_reports_errors_when_only_one_method_ran() {
+        let (value, report) = Experiment::new("test")
+            .control(async { Err::<i32, _>("control failed") })
+            .experimental(async { Ok::<_, &str>(2) })
+            .rollout_strategy(RolloutDecision::UseControl)
+            .run_result_with_report()
+            .await;
+
+        assert_eq!(value, Err("control failed"));
+        assert_eq!(report.outcome, Outcome::Error);
+        assert_eq!(report.returned, Returned::Control);
+
+        let (value, report) = Experiment::new("test")
+            .control(async { Ok::<_, &str>(1) })
+            .experimental(async { Err::<i32, _>("experimental failed") })
+            .rollout_strategy(RolloutDecision::UseExperimental)
+            .run_result_with_report()
+            .await;
+
+        assert_eq!(value, Err("experimental failed"));
+        assert_eq!(report.outcome, Outcome::Error);
+        assert_eq!(report.returned, Returned::Experimental);
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_control_when_experimental_returns_err() {
+        let (value, report) = Experiment::new("test")
+            .control(async { Ok::<_, &str>(1) })
+            .experimental(async { Err::<i32, _>("unavailable") })
+            .rollout_strategy(RolloutDecision::UseExperimentalWithFallback)
+            .run_result_with_report()
+            .await;
+
+        assert_eq!(value, Ok(1));
+        assert_eq!(report.outcome, Outcome::Fallback);
+        assert_eq!(report.returned, Returned::Control);
+        assert!(report.control_duration.is_some());
+        assert!(report.experimental_duration.is_some());
+
+        let (value, report) = Experiment::new("test")
+            .control(async { Ok::<_, &str>(1) })
+            .experimental(async { Ok::<_, &str>(2) })
+            .rollout_strategy(RolloutDecision::UseExperimentalWithFallback)
+            .run_result_with_report()
+            .await;
+
+        assert_eq!(value, Ok(2));
+        assert_eq!(report.outcome, Outcome::Ignored);
+        assert!(report.control_duration.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_control_when_experimental_panics() {
+        let (value, report) = Experiment::new("test")
+            .control(async { 1 })
+            .experimental(async { panic!("experimental exploded") })
+            .rollout_strategy(RolloutDecision::UseExperimentalWithFallback)
+            .run_with_report()
+            .await;
+
+        assert_eq!(value, 1);
+        assert_eq!(report.outcome, Outcome::Fallback);
+        assert_eq!(report.returned, Returned::Control);
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_outcome_of_a_panicking_experimental_before_falling_back() {
+        let recorder = RecordingObserver::new();
+
+        let (value, report) = Experiment::new("test")
+            .control(async { Ok::<_, &str>(1) })
+            .experimental(async { panic!("experimental exploded") })
+            .rollout_strategy(RolloutDecision::UseExperimentalWithFallback)
+            .observer(recorder.shared())
+            .run_result_with_report()
+            .await;
+
+        assert_eq!(value, Ok(1));
+        assert_eq!(report.outcome, Outcome::Fallback);
+
+        let outcomes: Vec<_> = recorder
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, Event::Outcome { .. }))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                Event::Outcome {
+                    name: "test",
+                    kind: "experimental",
+                    error: Some("panicked: experimental exploded".to_string()),
+                    category: None,
+                },
+                Event::Outcome {
+                    name: "test",
+                    kind: "control",
+                    error: None,
+                    category: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reports_errors_from_both_methods() {
+        let (value, report) = Experiment::new("test")
+            .control(async { Err::<bool, _>("control failed") })
+            .experimental(async { Err::<bool, _>("experimental failed") })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .run_result_with_report()
+            .await;
+
+        assert_eq!(value, Err("control failed"));
+        assert_eq!(report.outcome, Outcome::Error);
+        assert_eq!(report.returned, Returned::Control);
+    }
+
+    #[tokio::test]
+    async fn it_notifies_the_observer() {
+        let recorder = RecordingObserver::new();
+
+        let result = Experiment::new("observed")
+            .control(async { Ok::<_, &str>(true) })
+            .experimental(async { Err::<bool, _>("failed") })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .observer(recorder.shared())
+            .run_result()
+            .await;
+
+        assert_eq!(result, Ok(true));
+
+        let events = recorder
+            .events()
+            .into_iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    Event::Decision { .. } | Event::Outcome { .. } | Event::Mismatch { .. }
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                Event::Decision {
+                    name: "observed",
+                    decision: RolloutDecision::UseExperimentalAndCompare,
+                },
+                Event::Outcome {
+                    name: "observed",
+                    kind: "control",
+                    error: None,
+                    category: None,
+                },
+                Event::Outcome {
+                    name: "observed",
+                    kind: "experimental",
+                    error: Some("failed".to_string()),
+                    category: None,
+                },
+                Event::Mismatch {
+                    name: "observed",
+                    category: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_runs_sync_experiments() {
+        let mut calls = Vec::new();
+
+        let value = Experiment::new("test")
+            .control_fn(|| {
+                calls.push("control");
+                1
+            })
+            .experimental_fn(|| 2)
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|mismatch| mismatch.experimental)
+            .run_sync();
+
+        assert_eq!(value, 2);
+        assert_eq!(calls, vec!["control"]);
+    }
+
+    #[test]
+    fn it_runs_sync_result_experiments() {
+        let result = Experiment::new("test")
+            .control_fn(|| Ok::<_, &str>(true))
+            .experimental_fn(|| Err::<bool, _>("failed"))
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .run_result_sync();
+
+        assert_eq!(result, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn it_limits_experimental_concurrency() {
+        let recorder = RecordingObserver::new();
+        let (tx, rx) = futures::channel::oneshot::channel::<()>();
+
+        let first = Experiment::new("concurrency limited")
+            .control(async { 1 })
+            .experimental(async {
+                rx.await.unwrap();
+                1
+            })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .experimental_concurrency_limit(1)
+            .run_with_report();
+
+        let second = async {
+            let report = Experiment::new("concurrency limited")
+                .control(async { 2 })
+                .experimental(async { 2 })
+                .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+                .experimental_concurrency_limit(1)
+                .observer(recorder.shared())
+                .run_with_report()
+                .await
+                .1;
+
+            tx.send(()).unwrap();
+            report
+        };
+
+        let ((_, first), second) = futures::join!(first, second);
+
+        assert_eq!(first.decision, RolloutDecision::UseExperimentalAndCompare);
+        assert_eq!(second.decision, RolloutDecision::UseControl);
+
+        let skips = recorder
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, Event::Skipped { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            skips,
+            vec![Event::Skipped {
+                name: "concurrency limited",
+                reason: "concurrency",
+            }]
+        );
+    }
+
+    struct TokioSpawner;
+
+    impl runtime::Spawner for TokioSpawner {
+        fn spawn(&self, future: BoxFuture<'static, ()>) {
+            tokio::spawn(future);
+        }
+
+        fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+            tokio::task::spawn_blocking(f);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_returns_the_winner_of_a_race_and_compares_in_the_background() {
+        let (tx, rx) = futures::channel::oneshot::channel();
+
+        let value = Experiment::new("race")
+            .control(async {
+                tokio::task::yield_now().await;
+                1
+            })
+            .experimental(async { 2 })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(move |mismatch| {
+                tx.send((mismatch.control, mismatch.experimental)).unwrap();
+                mismatch.control
+            })
+            .race_on(Some(Arc::new(TokioSpawner)))
+            .await;
+
+        assert_eq!(value, 2);
+        assert_eq!(rx.await, Ok((1, 2)));
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_the_slower_method_when_the_winner_of_a_race_fails() {
+        let recorder = RecordingObserver::new();
+
+        let result = Experiment::new("race result")
+            .control(async {
+                tokio::task::yield_now().await;
+                Ok(1)
+            })
+            .experimental(async { Err("unavailable") })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .observer(recorder.shared())
+            .result_race_on(Some(Arc::new(TokioSpawner)))
+            .await;
+
+        assert_eq!(result, Ok(1));
+
+        let winners = recorder
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, Event::RaceWon { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            winners,
+            vec![Event::RaceWon {
+                name: "race result",
+                kind: "control",
+            }]
+        );
+    }
+
+    /// Race an experiment whose experimental wins and mismatches with the
+    /// control, returning the value and the recorded winners
+    async fn race_with_global_spawner() -> (u8, Vec<Event>) {
+        let recorder = RecordingObserver::new();
+
+        let value = Experiment::new("public race")
+            .control(async {
+                futures::pending!();
+                1
+            })
+            .experimental(async { 2 })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|_| 3)
+            .observer(recorder.shared())
+            .run_race()
+            .await;
+
+        let winners = recorder
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, Event::RaceWon { .. }))
+            .collect();
+        (value, winners)
+    }
+
+    /// `run_result_race` with a failing winner, so the control's value is used
+    async fn result_race_with_global_spawner() -> Result<u8, &'static str> {
+        Experiment::new("public result race")
+            .control(async {
+                futures::pending!();
+                Ok(1)
+            })
+            .experimental(async { Err("unavailable") })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|_| Ok(3))
+            .run_result_race()
+            .await
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn it_races_with_the_global_spawner() {
+        let (value, winners) = race_with_global_spawner().await;
+        assert_eq!(value, 2);
+        assert_eq!(
+            winners,
+            vec![Event::RaceWon {
+                name: "public race",
+                kind: "experimental",
+            }]
+        );
+
+        assert_eq!(result_race_with_global_spawner().await, Ok(1));
+    }
+
+    #[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+    #[tokio::test]
+    async fn it_races_without_a_spawner() {
+        assert!(runtime::global_spawner().is_none());
+
+        // The winner's value is returned, not the mismatch handler's
+        let (value, winners) = race_with_global_spawner().await;
+        assert_eq!(value, 2);
+        assert_eq!(
+            winners,
+            vec![Event::RaceWon {
+                name: "public race",
+                kind: "experimental",
+            }]
+        );
+
+        assert_eq!(result_race_with_global_spawner().await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn it_runs_blocking_experiments_in_parallel() {
+        let (control, experimental) = overlapping_closures();
+        let both_ran = Experiment::new("blocking")
+            .control_fn(control)
+            .experimental_fn(experimental)
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|_| false)
+            .into_blocking(Some(Arc::new(TokioSpawner)))
+            .run()
+            .await;
+
+        assert!(both_ran);
+    }
+
+    #[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+    #[tokio::test]
+    async fn it_runs_blocking_experiments_on_new_threads_without_a_spawner() {
+        assert!(runtime::global_spawner().is_none());
+
+        let (control, experimental) = overlapping_closures();
+        let both_ran = Experiment::new("blocking without a spawner")
+            .control_fn(control)
+            .experimental_fn(experimental)
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|_| false)
+            .run_blocking()
+            .await;
+
+        assert!(both_ran);
+    }
+
+    /// A control and experimental closure which each only return true if the
+    /// other one is running at the same time
+    fn overlapping_closures() -> (
+        impl FnOnce() -> bool + Send + 'static,
+        impl FnOnce() -> bool + Send + 'static,
+    ) {
+        use std::sync::mpsc;
+
+        let (control_tx, control_rx) = mpsc::channel();
+        let (experimental_tx, experimental_rx) = mpsc::channel();
+
+        let control = move || {
+            control_tx.send(()).unwrap();
+            experimental_rx.recv_timeout(Duration::from_secs(5)).is_ok()
+        };
+        let experimental = move || {
+            experimental_tx.send(()).unwrap();
+            control_rx.recv_timeout(Duration::from_secs(5)).is_ok()
+        };
+        (control, experimental)
+    }
+
+    #[tokio::test]
+    async fn it_classifies_mismatches() {
+        const CATEGORIES: [&str; 20] = [
+            "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q",
+            "r", "s", "t",
+        ];
+
+        let recorder = RecordingObserver::new();
+
+        for experimental in 1..=20 {
+            Experiment::new("classified")
+                .control(async { 0 })
+                .experimental(async move { experimental })
+                .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+                .classify_mismatch(|mismatch| CATEGORIES[mismatch.experimental - 1])
+                .observer(recorder.shared())
+                .run()
+                .await;
+        }
+
+        let categories = recorder
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Mismatch { category, .. } => Some(category),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(categories[0], Some("a"));
+        assert_eq!(categories[15], Some("p"));
+        assert_eq!(categories[16], Some("other"));
+        assert_eq!(categories[19], Some("other"));
+    }
+
+    #[tokio::test]
+    async fn it_reports_labels() {
+        let recorder = RecordingObserver::new();
+
+        for tenant in 0..=100 {
+            Experiment::new("labelled")
+                .control(async { 1 })
+                .experimental(async { 1 })
+                .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+                .label("tenant", "replaced")
+                .label("region", "eu")
+                .label("tenant", tenant.to_string())
+                .observer(recorder.shared())
+                .run()
+                .await;
+        }
+
+        let labels = recorder
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::RunStart { labels, .. } => Some(labels),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels[0],
+            [("tenant", "0".to_string()), ("region", "eu".to_string())]
+        );
+        assert_eq!(labels[99][0], ("tenant", "99".to_string()));
+        assert_eq!(labels[100][0], ("tenant", "__overflow__".to_string()));
+        assert_eq!(labels[100][1], ("region", "eu".to_string()));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "the kind label is reserved by thesis")]
+    fn it_rejects_reserved_labels() {
+        let _ = Experiment::<u8, _, _, _, _>::new("reserved").label("kind", "x");
+    }
+
+    #[tokio::test]
+    async fn it_classifies_errors() {
+        #[derive(Debug, PartialEq)]
+        enum LoadError {
+            Timeout,
+            NotFound,
+        }
+
+        impl LoadError {
+            fn category(&self) -> &'static str {
+                match self {
+                    LoadError::Timeout => "timeout",
+                    LoadError::NotFound => "not_found",
+                }
+            }
+        }
+
+        impl std::fmt::Display for LoadError {
+            fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(fmt, "{:?}", self)
+            }
+        }
+
+        let recorder = RecordingObserver::new();
+
+        let result = Experiment::new("classified errors")
+            .control(async { Err::<i32, _>(LoadError::Timeout) })
+            .experimental(async { Err::<i32, _>(LoadError::NotFound) })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .classify_error(LoadError::category)
+            .observer(recorder.shared())
+            .run_result()
+            .await;
+
+        assert_eq!(result, Err(LoadError::Timeout));
+
+        let categories = recorder
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Outcome {
+                    kind,
+                    error: Some(_),
+                    category,
+                    ..
+                } => Some((kind, category)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            categories,
+            vec![
+                ("control", Some("timeout")),
+                ("experimental", Some("not_found"))
+            ]
+        );
+    }
 }
@@ -0,0 +1,195 @@
+//! Paired latency statistics for experiments. Every run where both the control
+//! and experimental ran records how long each of them took, and the most
+//! recent `WINDOW` of these samples are kept in memory for each experiment, to
+//! be summarized with `summary`.
+//!
+//! ```
+//! use thesis::{latency, Experiment, RolloutDecision};
+//!
+//! # tokio_test::block_on(async {
+//! Experiment::new("render_v1 => render_v2")
+//!     .control(async { 1 })
+//!     .experimental(async { 1 })
+//!     .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+//!     .run()
+//!     .await;
+//!
+//! let summary = latency::summary("render_v1 => render_v2").unwrap();
+//! assert_eq!(summary.samples, 1);
+//! # });
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The number of most recent samples kept for each experiment
+pub const WINDOW: usize = 1024;
+
+/// The 50th, 95th and 99th percentiles of a set of samples
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Percentiles<T> {
+    pub p50: T,
+    pub p95: T,
+    pub p99: T,
+}
+
+/// A summary of the most recent paired durations of an experiment
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LatencySummary {
+    /// The number of runs the summary is based on, at most `WINDOW`
+    pub samples: usize,
+
+    /// How long the control method took
+    pub control: Percentiles<Duration>,
+
+    /// How long the experimental method took
+    pub experimental: Percentiles<Duration>,
+
+    /// How much longer the experimental method took than the control in the
+    /// same run, in seconds. Negative when the experimental was faster.
+    pub delta: Percentiles<f64>,
+}
+
+/// The durations of a single run where both methods ran
+struct Sample {
+    recorded_at: Instant,
+    control: Duration,
+    experimental: Duration,
+}
+
+/// Paired control and experimental durations, per experiment name
+type Samples = HashMap<&'static str, VecDeque<Sample>>;
+
+static SAMPLES: Mutex<Option<Samples>> = Mutex::new(None);
+
+fn samples() -> MutexGuard<'static, Option<Samples>> {
+    SAMPLES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Record the durations of a run where both methods ran
+pub(crate) fn record(name: &'static str, control: Duration, experimental: Duration) {
+    let mut samples = samples();
+    let window = samples
+        .get_or_insert_with(HashMap::new)
+        .entry(name)
+        .or_default();
+
+    if window.len() == WINDOW {
+        window.pop_front();
+    }
+    window.push_back(Sample {
+        recorded_at: Instant::now(),
+        control,
+        experimental,
+    });
+}
+
+/// Summarize the most recent paired durations of the named experiment, or
+/// `None` if no run of it has compared both methods yet
+pub fn summary(name: &str) -> Option<LatencySummary> {
+    summarize(name, None)
+}
+
+/// Like `summary`, but only for the samples recorded at or after `since`
+pub(crate) fn summary_since(name: &str, since: Instant) -> Option<LatencySummary> {
+    summarize(name, Some(since))
+}
+
+fn summarize(name: &str, since: Option<Instant>) -> Option<LatencySummary> {
+    let samples = samples();
+    let window = samples
+        .as_ref()?
+        .get(name)?
+        .iter()
+        .filter(|sample| match since {
+            Some(since) => sample.recorded_at >= since,
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    if window.is_empty() {
+        return None;
+    }
+
+    let mut control = window.iter().map(|s| s.control).collect::<Vec<_>>();
+    let mut experimental = window.iter().map(|s| s.experimental).collect::<Vec<_>>();
+    let mut delta = window
+        .iter()
+        .map(|s| s.experimental.as_secs_f64() - s.control.as_secs_f64())
+        .collect::<Vec<_>>();
+
+    control.sort();
+    experimental.sort();
+    delta.sort_by(f64::total_cmp);
+
+    Some(LatencySummary {
+        samples: window.len(),
+        control: percentiles(&control),
+        experimental: percentiles(&experimental),
+        delta: percentiles(&delta),
+    })
+}
+
+/// Forget the recorded durations of the named experiment
+pub fn reset(name: &str) {
+    if let Some(samples) = samples().as_mut() {
+        samples.remove(name);
+    }
+}
+
+/// Nearest-rank percentiles of samples which are already sorted
+fn percentiles<T: Copy>(sorted: &[T]) -> Percentiles<T> {
+    let rank = |p: f64| {
+        let index = (p * sorted.len() as f64).ceil() as usize;
+        sorted[index.clamp(1, sorted.len()) - 1]
+    };
+
+    Percentiles {
+        p50: rank(0.50),
+        p95: rank(0.95),
+        p99: rank(0.99),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_summarizes_paired_durations() {
+        assert_eq!(summary("summarized"), None);
+
+        for i in 1..=100 {
+            record(
+                "summarized",
+                Duration::from_millis(i),
+                Duration::from_millis(2 * i),
+            );
+        }
+
+        let summary = summary("summarized").unwrap();
+        assert_eq!(summary.samples, 100);
+        assert_eq!(summary.control.p50, Duration::from_millis(50));
+        assert_eq!(summary.control.p95, Duration::from_millis(95));
+        assert_eq!(summary.experimental.p99, Duration::from_millis(198));
+        assert!((summary.delta.p99 - 0.099).abs() < 1e-9);
+
+        reset("summarized");
+        assert_eq!(super::summary("summarized"), None);
+    }
+
+    #[test]
+    fn it_keeps_a_window_of_recent_samples() {
+        for i in 0..WINDOW as u64 + 10 {
+            record("windowed", Duration::from_millis(i), Duration::ZERO);
+        }
+
+        let summary = summary("windowed").unwrap();
+        assert_eq!(summary.samples, WINDOW);
+        assert_eq!(summary.control.p50, Duration::from_millis(10 + 511));
+    }
+}
@@ -41,10 +41,35 @@
 //! # });
 //! ```
 
+mod cardinality;
+pub mod classify;
+mod concurrency;
+pub mod config;
+mod error;
 pub mod experiment;
+pub mod latency;
+pub mod metadata;
 pub mod mismatch;
+pub mod observer;
+#[cfg(feature = "opentelemetry")]
+pub mod otel;
+pub mod prometheus;
+pub mod report;
 pub mod rollout;
+pub mod runtime;
+pub mod stats;
+pub mod stream;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod trace;
 
+pub use classify::ErrorClassifier;
+pub use error::Error;
 pub use experiment::Experiment;
 pub use mismatch::{Mismatch, MismatchHandler};
+pub use observer::ExperimentObserver;
+pub use report::Report;
 pub use rollout::{RolloutDecision, RolloutStrategy};
+pub use stream::StreamExperiment;
+#[cfg(feature = "macros")]
+pub use thesis_macros::{dual, experiment};
@@ -0,0 +1,296 @@
+//! Static metadata describing experiments, such as who owns them and when they
+//! should be cleaned up. Metadata is declared with `Experiment::metadata` or
+//! `declare`, added to the spans of every run of the experiment, and can be
+//! listed with `all`. Once an experiment has expired, its runs log a warning at
+//! most once an hour.
+//!
+//! ```
+//! use thesis::metadata::{self, Metadata};
+//!
+//! metadata::declare(
+//!     "load_data_from_db => load_data_from_redis",
+//!     Metadata::new()
+//!         .owner("storage")
+//!         .description("Serve reads from the Redis cache")
+//!         .ticket("STOR-1234")
+//!         .expires_on(2030, 6, 30)?,
+//! );
+//!
+//! let all = metadata::all();
+//! let metadata = all["load_data_from_db => load_data_from_redis"];
+//! assert_eq!(metadata.owner, Some("storage"));
+//! assert!(!metadata.is_expired());
+//! # Ok::<(), thesis::Error>(())
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::Error;
+
+/// Metadata describing an experiment
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Metadata {
+    /// The team or person responsible for the experiment
+    pub owner: Option<&'static str>,
+
+    /// What the experiment is for
+    pub description: Option<&'static str>,
+
+    /// The ticket tracking the experiment
+    pub ticket: Option<&'static str>,
+
+    /// When the experiment should have been cleaned up by
+    pub expires: Option<SystemTime>,
+}
+
+impl Metadata {
+    /// Create empty metadata
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the team or person responsible for the experiment
+    pub fn owner(self, owner: &'static str) -> Self {
+        Self {
+            owner: Some(owner),
+            ..self
+        }
+    }
+
+    /// Set what the experiment is for
+    pub fn description(self, description: &'static str) -> Self {
+        Self {
+            description: Some(description),
+            ..self
+        }
+    }
+
+    /// Set the ticket tracking the experiment
+    pub fn ticket(self, ticket: &'static str) -> Self {
+        Self {
+            ticket: Some(ticket),
+            ..self
+        }
+    }
+
+    /// Set when the experiment should have been cleaned up by
+    pub fn expires_at(self, expires: SystemTime) -> Self {
+        Self {
+            expires: Some(expires),
+            ..self
+        }
+    }
+
+    /// Make the experiment expire at the end of the given day, in UTC.
+    /// Returns `Error::InvalidDate` if the date doesn't exist or is before
+    /// 1970.
+    pub fn expires_on(self, year: i32, month: u32, day: u32) -> Result<Self, Error> {
+        let valid = (1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day);
+        let days = days_since_epoch(year, month, day) + 1;
+        if !valid || days <= 0 {
+            return Err(Error::InvalidDate(year, month, day));
+        }
+
+        Ok(self.expires_at(UNIX_EPOCH + Duration::from_secs(days as u64 * 24 * 60 * 60)))
+    }
+
+    /// Whether the experiment is past its expiry date
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| SystemTime::now() >= expires)
+    }
+
+    /// When the experiment should have been cleaned up by, in seconds since
+    /// the Unix epoch, for recording on spans
+    #[cfg(any(feature = "tracing", feature = "opentelemetry"))]
+    pub(crate) fn expires_unix_secs(&self) -> Option<u64> {
+        self.expires.map(|expires| {
+            expires
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        })
+    }
+}
+
+/// The number of days in the given month of the given year
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given date, see
+/// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_since_epoch(year: i32, month: u32, day: u32) -> i64 {
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * i64::from((month + 9) % 12) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// How often each expired experiment logs a warning
+const EXPIRY_WARNING_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+struct Entry {
+    metadata: Metadata,
+
+    /// Only locked by runs of the experiment once it has expired
+    last_warned: Mutex<Option<Instant>>,
+}
+
+/// Declared metadata, per experiment name
+type Registry = HashMap<&'static str, Entry>;
+
+static REGISTRY: RwLock<Option<Registry>> = RwLock::new(None);
+
+fn registry() -> RwLockReadGuard<'static, Option<Registry>> {
+    REGISTRY
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn registry_mut() -> RwLockWriteGuard<'static, Option<Registry>> {
+    REGISTRY
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Declare the metadata of the named experiment, replacing any metadata
+/// declared for it before. Declaring the same metadata again is cheap, so this
+/// can be called on every run.
+pub fn declare(name: &'static str, metadata: Metadata) {
+    if get(name) == Some(metadata) {
+        return;
+    }
+
+    let mut registry = registry_mut();
+    let registry = registry.get_or_insert_with(HashMap::new);
+
+    match registry.get_mut(name) {
+        Some(entry) => entry.metadata = metadata,
+        None => {
+            registry.insert(
+                name,
+                Entry {
+                    metadata,
+                    last_warned: Mutex::new(None),
+                },
+            );
+        }
+    }
+}
+
+/// The metadata declared for the named experiment, if any
+pub fn get(name: &str) -> Option<Metadata> {
+    registry().as_ref()?.get(name).map(|entry| entry.metadata)
+}
+
+/// The metadata of every experiment which has declared some, by name
+pub fn all() -> BTreeMap<&'static str, Metadata> {
+    registry()
+        .iter()
+        .flatten()
+        .map(|(name, entry)| (*name, entry.metadata))
+        .collect()
+}
+
+/// The metadata of the named experiment for a run which is starting, logging a
+/// warning if the experiment has expired and hasn't warned in the last
+/// `EXPIRY_WARNING_INTERVAL`
+pub(crate) fn for_run(name: &'static str) -> Metadata {
+    let registry = registry();
+    let entry = match registry.as_ref().and_then(|registry| registry.get(name)) {
+        Some(entry) => entry,
+        None => return Metadata::default(),
+    };
+
+    if entry.metadata.is_expired() {
+        let now = Instant::now();
+        let mut last_warned = entry
+            .last_warned
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let due = match *last_warned {
+            Some(warned) => now.duration_since(warned) >= EXPIRY_WARNING_INTERVAL,
+            None => true,
+        };
+
+        if due {
+            *last_warned = Some(now);
+
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                name,
+                owner = entry.metadata.owner,
+                ticket = entry.metadata.ticket,
+                "thesis experiment has expired and should be cleaned up"
+            );
+        }
+    }
+
+    entry.metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_expires_at_the_end_of_the_day() {
+        let metadata = Metadata::new().expires_on(2024, 2, 29).unwrap();
+        let expected = UNIX_EPOCH + Duration::from_secs(1_709_251_200);
+
+        assert_eq!(metadata.expires, Some(expected));
+        assert!(metadata.is_expired());
+        assert!(!Metadata::new().is_expired());
+    }
+
+    #[test]
+    fn it_only_warns_about_expiry_once_an_hour() {
+        declare("expired", Metadata::new().expires_on(2020, 1, 1).unwrap());
+
+        let last_warned = || {
+            *registry().as_ref().unwrap()["expired"]
+                .last_warned
+                .lock()
+                .unwrap()
+        };
+
+        for_run("expired");
+        let warned = last_warned();
+        assert!(warned.is_some());
+
+        for_run("expired");
+        assert_eq!(last_warned(), warned);
+
+        // Declaring the same metadata again keeps the entry as it was
+        declare("expired", Metadata::new().expires_on(2020, 1, 1).unwrap());
+        assert_eq!(last_warned(), warned);
+    }
+
+    #[test]
+    fn it_rejects_invalid_expiry_dates() {
+        assert_eq!(
+            Metadata::new().expires_on(2023, 2, 29),
+            Err(Error::InvalidDate(2023, 2, 29))
+        );
+        assert_eq!(
+            Metadata::new().expires_on(2023, 13, 1),
+            Err(Error::InvalidDate(2023, 13, 1))
+        );
+        assert_eq!(
+            Metadata::new().expires_on(1969, 12, 30),
+            Err(Error::InvalidDate(1969, 12, 30))
+        );
+    }
+}
@@ -1,6 +1,7 @@
 #[derive(Debug)]
 /// Type passed to the `on_mismatch` function, which is called when the control
 /// and experimental methods create different values.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mismatch<T> {
     /// The value generated by the control method
     pub control: T,
@@ -19,6 +20,7 @@ pub trait MismatchHandler<T> {
 
 /// A mismatch handler which always returns the value from the control function
 /// and does nothing else.
+#[derive(Clone, Copy)]
 pub struct AlwaysControl;
 
 impl<T> MismatchHandler<T> for AlwaysControl {
@@ -0,0 +1,361 @@
+use futures::future;
+#[cfg(feature = "metrics")]
+use metrics::{counter, histogram, Label};
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
+use crate::cardinality;
+#[cfg(feature = "metrics")]
+use crate::config::metric_names;
+use crate::report::Report;
+use crate::rollout::RolloutDecision;
+use crate::stats::{self, StatsObserver};
+
+/// The outcome of a single method, passed to `ExperimentObserver::on_outcome`.
+/// Only reported by `run_result`.
+#[derive(Clone, Copy)]
+pub enum BranchOutcome<'a> {
+    /// The method returned `Ok`
+    Ok,
+
+    /// The method returned `Err`. `category` is the category returned by the
+    /// classifier given to `Experiment::classify_error`, if there is one.
+    Error {
+        error: &'a dyn Display,
+        category: Option<&'static str>,
+    },
+}
+
+/// Context which an observer makes current while part of an experiment runs,
+/// such as a span of a tracing system, returned by
+/// `ExperimentObserver::run_scope` and `ExperimentObserver::branch_scope`. The
+/// scope is entered every time the part of the run it covers is polled, and
+/// dropped once that part has finished.
+pub trait Scope: Send + Sync {
+    /// Call `f` with the context of this scope made current
+    fn in_scope(&self, f: &mut dyn FnMut());
+}
+
+/// An `ExperimentObserver` is notified of everything that happens while an
+/// experiment runs, and is responsible for reporting it to metrics, logs or
+/// any other telemetry system. Every hook has an empty default implementation,
+/// so observers only need to implement the hooks they are interested in.
+///
+/// The `kind` passed to hooks is one of `control`, `experimental`,
+/// `experimental_and_compare` or `experimental_with_fallback`.
+pub trait ExperimentObserver: Send + Sync {
+    /// Called before an experiment with labels set with `Experiment::label`
+    /// starts running. Observers which can add labels to what they report
+    /// return a copy of themselves which does so, and that copy is used for the
+    /// rest of the run. By default, the labels are ignored.
+    fn with_labels(
+        &self,
+        _labels: &[(&'static str, String)],
+    ) -> Option<Arc<dyn ExperimentObserver>> {
+        None
+    }
+
+    /// Called when an experiment starts running, before the rollout decision
+    /// is made
+    fn on_run_start(&self, _name: &'static str) {}
+
+    /// Called right after `on_run_start`. Observers which need their own
+    /// context to be current while the experiment runs return it here. It is
+    /// entered whenever the run is polled, including the comparison `run_race`
+    /// finishes in the background, and is current when `on_run_end` is called.
+    fn run_scope(&self, _name: &'static str) -> Option<Arc<dyn Scope>> {
+        None
+    }
+
+    /// Called when the control or experimental method starts running, inside
+    /// the scope returned by `run_scope`. The returned scope is entered
+    /// whenever the method is polled, so it is current for the code the method
+    /// runs. Closures given to `run_blocking` run on another thread, outside of
+    /// it.
+    fn branch_scope(&self, _name: &'static str, _kind: &'static str) -> Option<Arc<dyn Scope>> {
+        None
+    }
+
+    /// Called with the decision made by the rollout strategy, after it has
+    /// been downgraded if the experimental was skipped
+    fn on_decision(&self, _name: &'static str, _decision: RolloutDecision) {}
+
+    /// Called when the experimental method was not run even though the rollout
+    /// strategy asked for it. `reason` is `concurrency` when the experiment's
+    /// experimental concurrency limit was reached.
+    fn on_skipped(&self, _name: &'static str, _reason: &'static str) {}
+
+    /// Called when the control or experimental method finishes, with how long
+    /// it took to run
+    fn on_branch_duration(&self, _name: &'static str, _kind: &'static str, _duration: Duration) {}
+
+    /// Called when the control or experimental method returns a `Result`
+    fn on_outcome(&self, _name: &'static str, _kind: &'static str, _outcome: BranchOutcome<'_>) {}
+
+    /// Called when both the control and experimental methods ran, with how
+    /// long each of them took
+    fn on_latency_delta(&self, _name: &'static str, _control: Duration, _experimental: Duration) {}
+
+    /// Called when the control and experimental methods produced different
+    /// values. `category` is the category returned by the function given to
+    /// `Experiment::classify_mismatch`, if there is one.
+    fn on_mismatch(&self, _name: &'static str, _category: Option<&'static str>) {}
+
+    /// Called when the experimental method of a
+    /// `RolloutDecision::UseExperimentalWithFallback` run failed, before the
+    /// control method is run instead. `reason` is the error it returned, or
+    /// its panic message.
+    fn on_fallback(&self, _name: &'static str, _reason: &dyn Display) {}
+
+    /// Called by `run_race` and `run_result_race` with the kind of the method
+    /// whose value was returned, as soon as it is known
+    fn on_race_won(&self, _name: &'static str, _kind: &'static str) {}
+
+    /// Called once the experiment has finished running
+    fn on_run_end(&self, _name: &'static str, _report: &Report) {}
+}
+
+/// The default observer, which reports the metrics listed in the crate README
+/// via the `metrics` crate, and logs errors via the `tracing` crate. Metrics
+/// are only reported when the `metrics` feature is enabled, and errors are only
+/// logged when the `tracing` feature is enabled.
+#[derive(Clone, Debug, Default)]
+pub struct MetricsTracingObserver {
+    /// Labels set with `Experiment::label`, added to every metric
+    #[cfg(feature = "metrics")]
+    labels: Vec<Label>,
+}
+
+impl MetricsTracingObserver {
+    /// Create the default observer
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsTracingObserver {
+    /// The given labels, followed by the labels set with `Experiment::label`
+    fn labels(&self, labels: &[(&'static str, &'static str)]) -> Vec<Label> {
+        labels
+            .iter()
+            .map(|&(key, value)| Label::new(key, value))
+            .chain(self.labels.iter().cloned())
+            .collect()
+    }
+
+    /// The labels of a `thesis_experiment_outcome` counter
+    fn outcome_labels(
+        &self,
+        name: &'static str,
+        kind: &'static str,
+        outcome: &'static str,
+        category: Option<&'static str>,
+    ) -> Vec<Label> {
+        let mut labels = vec![("name", name), ("kind", kind), ("outcome", outcome)];
+        if let Some(category) = category {
+            labels.push(("category", category));
+        }
+        self.labels(&labels)
+    }
+}
+
+impl ExperimentObserver for MetricsTracingObserver {
+    #[cfg(feature = "metrics")]
+    fn with_labels(
+        &self,
+        labels: &[(&'static str, String)],
+    ) -> Option<Arc<dyn ExperimentObserver>> {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| Label::new(*key, value.clone()))
+            .collect();
+        Some(Arc::new(MetricsTracingObserver { labels }))
+    }
+
+    #[cfg(feature = "metrics")]
+    fn on_run_start(&self, name: &'static str) {
+        counter!(
+            metric_names().run_total.clone(),
+            self.labels(&[("name", name)])
+        )
+        .increment(1);
+    }
+
+    #[cfg(feature = "metrics")]
+    fn on_decision(&self, name: &'static str, decision: RolloutDecision) {
+        counter!(
+            metric_names().run_variant.clone(),
+            self.labels(&[("name", name), ("kind", decision.kind())]),
+        )
+        .increment(1);
+    }
+
+    #[cfg(feature = "metrics")]
+    fn on_skipped(&self, name: &'static str, reason: &'static str) {
+        counter!(
+            metric_names().skipped.clone(),
+            self.labels(&[("name", name), ("reason", reason)]),
+        )
+        .increment(1);
+    }
+
+    #[cfg(feature = "metrics")]
+    fn on_branch_duration(&self, name: &'static str, kind: &'static str, duration: Duration) {
+        let names = metric_names();
+        histogram!(
+            names.run_duration.clone(),
+            self.labels(&[("name", name), ("kind", kind)]),
+        )
+        .record(names.duration(duration));
+    }
+
+    #[cfg(feature = "metrics")]
+    fn on_latency_delta(&self, name: &'static str, control: Duration, experimental: Duration) {
+        let names = metric_names();
+        let delta = experimental.as_secs_f64() - control.as_secs_f64();
+        histogram!(names.latency_delta.clone(), self.labels(&[("name", name)]))
+            .record(names.duration_unit.from_secs(delta));
+    }
+
+    #[cfg(any(feature = "metrics", feature = "tracing"))]
+    fn on_outcome(&self, name: &'static str, kind: &'static str, outcome: BranchOutcome<'_>) {
+        match outcome {
+            BranchOutcome::Ok => {
+                #[cfg(feature = "metrics")]
+                counter!(
+                    metric_names().outcome.clone(),
+                    self.outcome_labels(name, kind, "ok", None),
+                )
+                .increment(1);
+            }
+            BranchOutcome::Error { error, category } => {
+                #[cfg(feature = "metrics")]
+                counter!(
+                    metric_names().outcome.clone(),
+                    self.outcome_labels(name, kind, "error", category),
+                )
+                .increment(1);
+
+                #[cfg(feature = "tracing")]
+                tracing::error!(name, kind, category, %error, "thesis experiment error");
+
+                #[cfg(not(feature = "tracing"))]
+                let _ = error;
+            }
+        }
+    }
+
+    #[cfg(any(feature = "metrics", feature = "tracing"))]
+    fn on_mismatch(&self, name: &'static str, category: Option<&'static str>) {
+        #[cfg(feature = "metrics")]
+        counter!(
+            metric_names().outcome.clone(),
+            self.outcome_labels(name, "experimental_and_compare", "mismatch", category),
+        )
+        .increment(1);
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(name, category, "thesis experiment mismatch");
+    }
+
+    #[cfg(any(feature = "metrics", feature = "tracing"))]
+    fn on_fallback(&self, name: &'static str, reason: &dyn Display) {
+        #[cfg(feature = "metrics")]
+        counter!(
+            metric_names().outcome.clone(),
+            self.outcome_labels(name, "experimental_with_fallback", "fallback", None),
+        )
+        .increment(1);
+
+        #[cfg(feature = "tracing")]
+        tracing::warn!(name, %reason, "thesis experiment falling back to control");
+
+        #[cfg(not(feature = "tracing"))]
+        let _ = reason;
+    }
+
+    #[cfg(feature = "metrics")]
+    fn on_race_won(&self, name: &'static str, kind: &'static str) {
+        counter!(
+            metric_names().race_won.clone(),
+            self.labels(&[("name", name), ("kind", kind)]),
+        )
+        .increment(1);
+    }
+}
+
+static GLOBAL_OBSERVER: RwLock<Option<Arc<dyn ExperimentObserver>>> = RwLock::new(None);
+
+/// Use the given observer for every experiment which doesn't have an observer
+/// set with `Experiment::observer`. Replaces any previously set global
+/// observer.
+pub fn set_global_observer<O>(observer: O)
+where
+    O: ExperimentObserver + 'static,
+{
+    *GLOBAL_OBSERVER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(observer));
+}
+
+/// The observer used by experiments which don't have an observer set with
+/// `Experiment::observer`. This is a `MetricsTracingObserver` unless
+/// `set_global_observer` has been called.
+pub fn global_observer() -> Arc<dyn ExperimentObserver> {
+    static DEFAULT: OnceLock<Arc<dyn ExperimentObserver>> = OnceLock::new();
+
+    GLOBAL_OBSERVER
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+        .unwrap_or_else(|| {
+            DEFAULT
+                .get_or_init(|| Arc::new(MetricsTracingObserver::new()))
+                .clone()
+        })
+}
+
+/// Run the future with the given scope entered every time it is polled
+pub(crate) async fn scoped<F>(scope: Option<Arc<dyn Scope>>, future: F) -> F::Output
+where
+    F: Future,
+{
+    let scope = match scope {
+        Some(scope) => scope,
+        None => return future.await,
+    };
+
+    futures::pin_mut!(future);
+    future::poll_fn(|cx| {
+        let mut poll = None;
+        scope.in_scope(&mut || poll = Some(future.as_mut().poll(cx)));
+        poll.expect("Scope::in_scope didn't call the function it was given")
+    })
+    .await
+}
+
+/// The observer a single run of the named experiment reports to: the given
+/// observer or else the global observer, with the labels set with
+/// `Experiment::label`, wrapped to count the run in `thesis::stats`
+pub(crate) fn for_run(
+    name: &'static str,
+    observer: Option<Arc<dyn ExperimentObserver>>,
+    labels: &[(&'static str, String)],
+) -> StatsObserver {
+    let mut observer = observer.unwrap_or_else(global_observer);
+
+    if !labels.is_empty() {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| (*key, cardinality::label(name, key, value)))
+            .collect::<Vec<_>>();
+        if let Some(labelled) = observer.with_labels(&labels) {
+            observer = labelled;
+        }
+    }
+
+    stats::observe(name, observer)
+}
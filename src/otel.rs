@@ -0,0 +1,513 @@
+//! An `ExperimentObserver` which reports to OpenTelemetry, for services whose
+//! telemetry pipeline is built on OpenTelemetry rather than the `metrics` and
+//! `tracing` crates.
+
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::metrics::{Counter, Histogram, Meter};
+use opentelemetry::trace::{Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{metrics_config, DurationUnit};
+use crate::metadata;
+use crate::observer::{BranchOutcome, ExperimentObserver, Scope};
+use crate::report::{Outcome, Report};
+use crate::rollout::RolloutDecision;
+
+/// An observer which records the metrics listed in the crate README as
+/// OpenTelemetry instruments with the same names and attributes, including
+/// labels set with `Experiment::label`, and records an
+/// `Experiment::run` span for each run with a child span for each method that
+/// ran. The run span has the owner, description, ticket and expiry date from
+/// `thesis::metadata` as attributes, if they were declared, with the expiry
+/// date in seconds since the Unix epoch.
+///
+/// The run span is a child of the OpenTelemetry context current when the
+/// experiment starts running, and each method's span is current while the
+/// method runs, so spans created by the method are its children.
+///
+/// The names and units of the instruments follow the `thesis::config` metrics
+/// configuration at the time the observer is created.
+///
+/// ```no_run
+/// use thesis::{observer, otel::OtelObserver};
+///
+/// observer::set_global_observer(OtelObserver::new());
+/// ```
+#[derive(Clone)]
+pub struct OtelObserver {
+    tracer: Arc<BoxedTracer>,
+    runs: Counter<u64>,
+    variants: Counter<u64>,
+    skipped: Counter<u64>,
+    outcomes: Counter<u64>,
+    races_won: Counter<u64>,
+    durations: Histogram<f64>,
+    latency_deltas: Histogram<f64>,
+    duration_unit: DurationUnit,
+
+    /// Labels set with `Experiment::label`, added to every measurement
+    labels: Vec<KeyValue>,
+}
+
+impl OtelObserver {
+    /// Report to the globally registered tracer and meter providers
+    pub fn new() -> Self {
+        Self::with_tracer_and_meter(global::tracer("thesis"), &global::meter("thesis"))
+    }
+
+    /// Report to the given tracer and meter
+    pub fn with_tracer_and_meter<T>(tracer: T, meter: &Meter) -> Self
+    where
+        T: Tracer + Send + Sync + 'static,
+        T::Span: Send + Sync + 'static,
+    {
+        let config = metrics_config();
+        let unit = config.duration_unit;
+
+        Self {
+            tracer: Arc::new(BoxedTracer::new(Box::new(tracer))),
+            runs: meter
+                .u64_counter(config.name("thesis_experiment_run_total"))
+                .build(),
+            variants: meter
+                .u64_counter(config.name("thesis_experiment_run_variant"))
+                .build(),
+            skipped: meter
+                .u64_counter(config.name("thesis_experiment_skipped"))
+                .build(),
+            outcomes: meter
+                .u64_counter(config.name("thesis_experiment_outcome"))
+                .build(),
+            races_won: meter
+                .u64_counter(config.name("thesis_experiment_race_won"))
+                .build(),
+            durations: meter
+                .f64_histogram(config.name("thesis_experiment_run_duration"))
+                .with_unit(unit.symbol())
+                .build(),
+            latency_deltas: meter
+                .f64_histogram(config.name("thesis_experiment_latency_delta"))
+                .with_unit(unit.symbol())
+                .build(),
+            duration_unit: unit,
+            labels: Vec::new(),
+        }
+    }
+
+    /// The given attributes, followed by the labels set with `Experiment::label`
+    fn attributes(&self, attributes: &[(&'static str, &'static str)]) -> Vec<KeyValue> {
+        attributes
+            .iter()
+            .map(|&(key, value)| KeyValue::new(key, value))
+            .chain(self.labels.iter().cloned())
+            .collect()
+    }
+
+    /// The attributes of a `thesis_experiment_outcome` measurement
+    fn outcome_attributes(
+        &self,
+        name: &'static str,
+        kind: &'static str,
+        outcome: &'static str,
+        category: Option<&'static str>,
+    ) -> Vec<KeyValue> {
+        let mut attributes = vec![("name", name), ("kind", kind), ("outcome", outcome)];
+        if let Some(category) = category {
+            attributes.push(("category", category));
+        }
+        self.attributes(&attributes)
+    }
+}
+
+impl Default for OtelObserver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The name of the experiment a run span belongs to, stored in the context of
+/// the span
+#[derive(Clone, Copy, PartialEq)]
+struct Run(&'static str);
+
+/// A span which is current while the part of a run it covers is polled, and
+/// ends once that part has finished
+struct SpanScope(Context);
+
+impl Scope for SpanScope {
+    fn in_scope(&self, f: &mut dyn FnMut()) {
+        let _guard = self.0.clone().attach();
+        f()
+    }
+}
+
+impl Drop for SpanScope {
+    fn drop(&mut self) {
+        self.0.span().end();
+    }
+}
+
+/// The value of the `thesis.experiment.outcome` span attribute
+fn outcome_label(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Match => "match",
+        Outcome::Mismatch => "mismatch",
+        Outcome::Error => "error",
+        Outcome::Ignored => "ignored",
+        Outcome::Fallback => "fallback",
+    }
+}
+
+impl ExperimentObserver for OtelObserver {
+    fn with_labels(
+        &self,
+        labels: &[(&'static str, String)],
+    ) -> Option<Arc<dyn ExperimentObserver>> {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| KeyValue::new(*key, value.clone()))
+            .collect();
+        Some(Arc::new(OtelObserver {
+            labels,
+            ..self.clone()
+        }))
+    }
+
+    fn on_run_start(&self, name: &'static str) {
+        self.runs.add(1, &self.attributes(&[("name", name)]));
+    }
+
+    fn run_scope(&self, name: &'static str) -> Option<Arc<dyn Scope>> {
+        let mut attributes = vec![KeyValue::new("thesis.experiment.name", name)];
+        let metadata = metadata::get(name).unwrap_or_default();
+        if let Some(owner) = metadata.owner {
+            attributes.push(KeyValue::new("thesis.experiment.owner", owner));
+        }
+        if let Some(description) = metadata.description {
+            attributes.push(KeyValue::new("thesis.experiment.description", description));
+        }
+        if let Some(ticket) = metadata.ticket {
+            attributes.push(KeyValue::new("thesis.experiment.ticket", ticket));
+        }
+        if let Some(expires) = metadata.expires_unix_secs() {
+            attributes.push(KeyValue::new("thesis.experiment.expires", expires as i64));
+        }
+
+        let span = self
+            .tracer
+            .span_builder("Experiment::run")
+            .with_attributes(attributes)
+            .start(&*self.tracer);
+        let cx = Context::current_with_span(span).with_value(Run(name));
+
+        Some(Arc::new(SpanScope(cx)))
+    }
+
+    fn branch_scope(&self, name: &'static str, kind: &'static str) -> Option<Arc<dyn Scope>> {
+        let span = self
+            .tracer
+            .span_builder(match kind {
+                "control" => "Experiment::control",
+                _ => "Experiment::experimental",
+            })
+            .with_attributes([
+                KeyValue::new("thesis.experiment.name", name),
+                KeyValue::new("thesis.experiment.kind", kind),
+            ])
+            .start(&*self.tracer);
+
+        Some(Arc::new(SpanScope(Context::current_with_span(span))))
+    }
+
+    fn on_decision(&self, name: &'static str, decision: RolloutDecision) {
+        self.variants.add(
+            1,
+            &self.attributes(&[("name", name), ("kind", decision.kind())]),
+        );
+    }
+
+    fn on_skipped(&self, name: &'static str, reason: &'static str) {
+        self.skipped
+            .add(1, &self.attributes(&[("name", name), ("reason", reason)]));
+    }
+
+    fn on_branch_duration(&self, name: &'static str, kind: &'static str, duration: Duration) {
+        self.durations.record(
+            self.duration_unit.from_secs(duration.as_secs_f64()),
+            &self.attributes(&[("name", name), ("kind", kind)]),
+        );
+    }
+
+    fn on_outcome(&self, name: &'static str, kind: &'static str, outcome: BranchOutcome<'_>) {
+        let attributes = match outcome {
+            BranchOutcome::Ok => self.outcome_attributes(name, kind, "ok", None),
+            BranchOutcome::Error { category, .. } => {
+                self.outcome_attributes(name, kind, "error", category)
+            }
+        };
+        self.outcomes.add(1, &attributes);
+    }
+
+    fn on_latency_delta(&self, name: &'static str, control: Duration, experimental: Duration) {
+        let delta = experimental.as_secs_f64() - control.as_secs_f64();
+        self.latency_deltas.record(
+            self.duration_unit.from_secs(delta),
+            &self.attributes(&[("name", name)]),
+        );
+    }
+
+    fn on_mismatch(&self, name: &'static str, category: Option<&'static str>) {
+        self.outcomes.add(
+            1,
+            &self.outcome_attributes(name, "experimental_and_compare", "mismatch", category),
+        );
+    }
+
+    fn on_fallback(&self, name: &'static str, _reason: &dyn Display) {
+        self.outcomes.add(
+            1,
+            &self.outcome_attributes(name, "experimental_with_fallback", "fallback", None),
+        );
+    }
+
+    fn on_race_won(&self, name: &'static str, kind: &'static str) {
+        self.races_won
+            .add(1, &self.attributes(&[("name", name), ("kind", kind)]));
+    }
+
+    fn on_run_end(&self, name: &'static str, report: &Report) {
+        let cx = Context::current();
+        if cx.get::<Run>() != Some(&Run(name)) {
+            return;
+        }
+
+        let run = cx.span();
+        run.set_attribute(KeyValue::new(
+            "thesis.experiment.decision",
+            report.decision.kind(),
+        ));
+        run.set_attribute(KeyValue::new(
+            "thesis.experiment.outcome",
+            outcome_label(report.outcome),
+        ));
+        if report.outcome == Outcome::Error {
+            run.set_status(Status::error("both methods returned an error"));
+        }
+        run.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::BoxFuture;
+    use futures::stream::{self, StreamExt};
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry::trace::{Span, TracerProvider};
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData};
+    use opentelemetry_sdk::metrics::{InMemoryMetricExporter, PeriodicReader, SdkMeterProvider};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+    use std::sync::Mutex;
+
+    use crate::runtime::Spawner;
+    use crate::stream::StreamExperiment;
+    use crate::Experiment;
+
+    /// A span exporter, and an observer whose spans are exported to it
+    fn exported_spans() -> (InMemorySpanExporter, SdkTracerProvider, OtelObserver) {
+        let spans = InMemorySpanExporter::default();
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_simple_exporter(spans.clone())
+            .build();
+        let observer = OtelObserver::with_tracer_and_meter(
+            tracer_provider.tracer("test"),
+            &SdkMeterProvider::default().meter("test"),
+        );
+
+        (spans, tracer_provider, observer)
+    }
+
+    fn span<'a>(spans: &'a [SpanData], name: &str) -> &'a SpanData {
+        spans
+            .iter()
+            .find(|span| span.name == name)
+            .unwrap_or_else(|| panic!("no {} span", name))
+    }
+
+    /// Holds spawned futures until the test awaits them
+    #[derive(Default)]
+    struct Deferred(Mutex<Vec<BoxFuture<'static, ()>>>);
+
+    impl Spawner for Deferred {
+        fn spawn(&self, future: BoxFuture<'static, ()>) {
+            self.0.lock().unwrap().push(future);
+        }
+
+        fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+            f()
+        }
+    }
+
+    #[tokio::test]
+    async fn it_records_spans_and_instruments() {
+        let spans = InMemorySpanExporter::default();
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_simple_exporter(spans.clone())
+            .build();
+        let metrics = InMemoryMetricExporter::default();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(metrics.clone()).build())
+            .build();
+
+        let observer = OtelObserver::with_tracer_and_meter(
+            tracer_provider.tracer("test"),
+            &meter_provider.meter("test"),
+        );
+        let tracer = tracer_provider.tracer("test");
+        Experiment::new("otel")
+            .control(async { 1 })
+            .experimental(async move {
+                tracer.start("query").end();
+                2
+            })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .observer(Arc::new(observer))
+            .run()
+            .await;
+
+        let spans = spans.get_finished_spans().unwrap();
+        let mut names = spans.iter().map(|span| &*span.name).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "Experiment::control",
+                "Experiment::experimental",
+                "Experiment::run",
+                "query"
+            ]
+        );
+        assert_eq!(
+            span(&spans, "query").parent_span_id,
+            span(&spans, "Experiment::experimental")
+                .span_context
+                .span_id()
+        );
+
+        let run = spans
+            .iter()
+            .find(|span| span.name == "Experiment::run")
+            .unwrap();
+        assert!(run.attributes.contains(&KeyValue::new(
+            "thesis.experiment.decision",
+            "experimental_and_compare"
+        )));
+        assert!(run
+            .attributes
+            .contains(&KeyValue::new("thesis.experiment.outcome", "mismatch")));
+        for branch in spans
+            .iter()
+            .filter(|span| span.name.starts_with("Experiment::") && span.name != "Experiment::run")
+        {
+            assert_eq!(branch.parent_span_id, run.span_context.span_id());
+            assert_eq!(branch.span_context.trace_id(), run.span_context.trace_id());
+        }
+
+        meter_provider.force_flush().unwrap();
+        let metrics = metrics.get_finished_metrics().unwrap();
+        let metric = |name: &str| {
+            metrics
+                .iter()
+                .flat_map(|resource| resource.scope_metrics())
+                .flat_map(|scope| scope.metrics())
+                .find(|metric| metric.name() == name)
+                .map(|metric| metric.data())
+        };
+
+        match metric("thesis_experiment_outcome") {
+            Some(AggregatedMetrics::U64(MetricData::Sum(sum))) => {
+                let point = sum.data_points().next().unwrap();
+                assert_eq!(point.value(), 1);
+                assert!(point
+                    .attributes()
+                    .any(|kv| kv == &KeyValue::new("outcome", "mismatch")));
+            }
+            other => panic!("unexpected outcome metric {:?}", other),
+        }
+        match metric("thesis_experiment_run_duration") {
+            Some(AggregatedMetrics::F64(MetricData::Histogram(histogram))) => {
+                let count = histogram
+                    .data_points()
+                    .map(|point| point.count())
+                    .sum::<u64>();
+                assert_eq!(count, 2);
+            }
+            other => panic!("unexpected duration metric {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_records_races_compared_in_the_background() {
+        let (spans, _provider, observer) = exported_spans();
+        let spawner = Arc::new(Deferred::default());
+
+        Experiment::new("otel race")
+            .control(async { 1 })
+            .experimental(async { 2 })
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .observer(Arc::new(observer))
+            .race_on(Some(spawner.clone()))
+            .await;
+
+        let comparisons = std::mem::take(&mut *spawner.0.lock().unwrap());
+        for comparison in comparisons {
+            comparison.await;
+        }
+
+        let spans = spans.get_finished_spans().unwrap();
+        let run = span(&spans, "Experiment::run");
+        assert!(run
+            .attributes
+            .contains(&KeyValue::new("thesis.experiment.outcome", "mismatch")));
+        for name in ["Experiment::control", "Experiment::experimental"] {
+            assert_eq!(
+                span(&spans, name).parent_span_id,
+                run.span_context.span_id()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn it_records_stream_experiments() {
+        let (spans, provider, observer) = exported_spans();
+        let tracer = provider.tracer("test");
+
+        StreamExperiment::new("otel stream")
+            .control(stream::iter(vec![1, 2]))
+            .experimental(stream::iter(vec![1, 2]).inspect(move |_| tracer.start("item").end()))
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .observer(Arc::new(observer))
+            .run()
+            .collect::<Vec<_>>()
+            .await;
+
+        let spans = spans.get_finished_spans().unwrap();
+        let run = span(&spans, "Experiment::run");
+        assert!(run
+            .attributes
+            .contains(&KeyValue::new("thesis.experiment.outcome", "match")));
+        assert_eq!(
+            span(&spans, "Experiment::experimental").parent_span_id,
+            run.span_context.span_id()
+        );
+        assert_eq!(
+            span(&spans, "item").parent_span_id,
+            span(&spans, "Experiment::experimental")
+                .span_context
+                .span_id()
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Rendering of the statistics from `thesis::stats` in the Prometheus text
+//! exposition format, for services which don't install a `metrics` recorder.
+//! The metrics have the same names and labels as the ones reported by the
+//! default observer, and follow the `thesis::config` metrics configuration.
+//! `thesis_experiment_outcome` also has an `outcome="match"` series, which the
+//! default observer doesn't report, counting the runs which compared both
+//! methods and found them equal.
+//!
+//! ```
+//! use thesis::{prometheus, Experiment, RolloutDecision};
+//!
+//! # tokio_test::block_on(async {
+//! Experiment::new("send_v1 => send_v2")
+//!     .control(async { 1 })
+//!     .experimental(async { 1 })
+//!     .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+//!     .run()
+//!     .await;
+//!
+//! let text = prometheus::render();
+//! assert!(text.contains("thesis_experiment_run_total{name=\"send_v1 => send_v2\"} 1\n"));
+//! # });
+//! ```
+
+use std::fmt::Write;
+
+use crate::config::{metrics_config, DurationUnit, MetricsConfig};
+use crate::stats::{self, BranchStats, Snapshot, DURATION_BUCKETS};
+
+/// Render the statistics of every experiment which has run
+pub fn render() -> String {
+    render_snapshot(&stats::snapshot())
+}
+
+/// Render a snapshot taken with `stats::snapshot`
+pub fn render_snapshot(snapshot: &Snapshot) -> String {
+    render_with_config(snapshot, &metrics_config())
+}
+
+/// Render a snapshot with the metric names and units in `config`
+fn render_with_config(snapshot: &Snapshot, config: &MetricsConfig) -> String {
+    let mut out = String::new();
+    let experiments = &snapshot.experiments;
+
+    let run_total = config.name("thesis_experiment_run_total");
+    let run_variant = config.name("thesis_experiment_run_variant");
+    let outcome = config.name("thesis_experiment_outcome");
+    let skipped = config.name("thesis_experiment_skipped");
+    let run_duration = config.name("thesis_experiment_run_duration");
+
+    header(
+        &mut out,
+        &run_total,
+        "counter",
+        "Number of times each experiment was run",
+    );
+    for (name, stats) in experiments {
+        sample(&mut out, &run_total, &[("name", name)], stats.runs);
+    }
+
+    header(
+        &mut out,
+        &run_variant,
+        "counter",
+        "Number of runs which made each rollout decision",
+    );
+    for (name, stats) in experiments {
+        let decisions = &stats.decisions;
+        for (kind, value) in [
+            ("control", decisions.control),
+            ("experimental", decisions.experimental),
+            (
+                "experimental_and_compare",
+                decisions.experimental_and_compare,
+            ),
+            (
+                "experimental_with_fallback",
+                decisions.experimental_with_fallback,
+            ),
+        ] {
+            sample(
+                &mut out,
+                &run_variant,
+                &[("name", name), ("kind", kind)],
+                value,
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        &outcome,
+        "counter",
+        "Number of observable outcomes of each experiment",
+    );
+    for (name, stats) in experiments {
+        for (kind, result, value) in [
+            ("control", "ok", stats.control.ok),
+            ("control", "error", stats.control.errors),
+            ("experimental", "ok", stats.experimental.ok),
+            ("experimental", "error", stats.experimental.errors),
+            ("experimental_and_compare", "match", stats.matches),
+            ("experimental_and_compare", "mismatch", stats.mismatches),
+            ("experimental_with_fallback", "fallback", stats.fallbacks),
+        ] {
+            sample(
+                &mut out,
+                &outcome,
+                &[("name", name), ("kind", kind), ("outcome", result)],
+                value,
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        &skipped,
+        "counter",
+        "Number of times the experimental was skipped even though the rollout strategy asked for it",
+    );
+    for (name, stats) in experiments {
+        sample(&mut out, &skipped, &[("name", name)], stats.skipped);
+    }
+
+    header(
+        &mut out,
+        &run_duration,
+        "histogram",
+        match config.duration_unit {
+            DurationUnit::Seconds => "How long each method took, in seconds",
+            DurationUnit::Milliseconds => "How long each method took, in milliseconds",
+        },
+    );
+    for (name, stats) in experiments {
+        for (kind, branch) in [
+            ("control", &stats.control),
+            ("experimental", &stats.experimental),
+        ] {
+            histogram(
+                &mut out,
+                &run_duration,
+                config.duration_unit,
+                name,
+                kind,
+                branch,
+            );
+        }
+    }
+
+    out
+}
+
+fn header(out: &mut String, metric: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", metric, help);
+    let _ = writeln!(out, "# TYPE {} {}", metric, kind);
+}
+
+fn sample(out: &mut String, metric: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    out.push_str(metric);
+    out.push('{');
+    for (i, (key, value)) in labels.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{}=\"{}\"", key, escape(value));
+    }
+    let _ = writeln!(out, "}} {}", value);
+}
+
+fn histogram(
+    out: &mut String,
+    metric: &str,
+    unit: DurationUnit,
+    name: &str,
+    kind: &str,
+    branch: &BranchStats,
+) {
+    let duration = &branch.duration;
+
+    for (upper_bound, count) in DURATION_BUCKETS.iter().zip(&duration.buckets) {
+        sample(
+            out,
+            &format!("{}_bucket", metric),
+            &[
+                ("name", name),
+                ("kind", kind),
+                ("le", &unit.from_secs(*upper_bound).to_string()),
+            ],
+            count,
+        );
+    }
+    sample(
+        out,
+        &format!("{}_bucket", metric),
+        &[("name", name), ("kind", kind), ("le", "+Inf")],
+        duration.count,
+    );
+    sample(
+        out,
+        &format!("{}_sum", metric),
+        &[("name", name), ("kind", kind)],
+        unit.from_secs(duration.sum.as_secs_f64()),
+    );
+    sample(
+        out,
+        &format!("{}_count", metric),
+        &[("name", name), ("kind", kind)],
+        duration.count,
+    );
+}
+
+/// Escape a label value as required by the text exposition format
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Serve `render` over HTTP on the given address from a background thread, for
+/// services which don't have an HTTP server of their own. `GET /metrics` returns
+/// the rendered statistics, and every other request a 404. Returns the address
+/// the server is listening on, which is useful when binding to port 0.
+///
+/// Connections are handled one at a time, and a client which takes longer than
+/// 5 seconds to send its request or receive the response is disconnected.
+#[cfg(feature = "prometheus-server")]
+pub fn serve<A>(addr: A) -> std::io::Result<std::net::SocketAddr>
+where
+    A: std::net::ToSocketAddrs,
+{
+    let listener = std::net::TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+
+    std::thread::Builder::new()
+        .name("thesis-prometheus".to_string())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(_error) = respond(stream) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(error = %_error, "failed to serve thesis metrics");
+                }
+            }
+        })?;
+
+    Ok(local_addr)
+}
+
+/// How long `serve` waits for a client to send its request or receive the
+/// response, so a slow or idle client can't block the scrapes after it
+#[cfg(all(feature = "prometheus-server", not(test)))]
+const CLIENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Short enough that tests with an idle client don't wait for the real timeout
+#[cfg(all(feature = "prometheus-server", test))]
+const CLIENT_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+#[cfg(feature = "prometheus-server")]
+fn respond(stream: std::net::TcpStream) -> std::io::Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Skip the headers, the request has no body
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        _ => ("404 Not Found", String::new()),
+    };
+
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::stats::{ExperimentStats, Histogram};
+
+    fn snapshot() -> Snapshot {
+        let mut stats = ExperimentStats {
+            runs: 3,
+            mismatches: 1,
+            ..ExperimentStats::default()
+        };
+        stats.decisions.experimental_and_compare = 3;
+        stats.control.duration = Histogram {
+            buckets: vec![1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3],
+            count: 3,
+            sum: Duration::from_millis(5030),
+        };
+
+        let mut snapshot = Snapshot::default();
+        snapshot.experiments.insert("say \"hi\"".to_string(), stats);
+        snapshot
+    }
+
+    #[test]
+    fn it_renders_counters_and_histograms() {
+        let text = render_snapshot(&snapshot());
+        let name = "name=\"say \\\"hi\\\"\"";
+
+        assert!(text.contains("# TYPE thesis_experiment_run_total counter\n"));
+        assert!(text.contains(&format!("thesis_experiment_run_total{{{}}} 3\n", name)));
+        assert!(text.contains(&format!(
+            "thesis_experiment_run_variant{{{},kind=\"experimental_and_compare\"}} 3\n",
+            name
+        )));
+        assert!(text.contains(&format!(
+            "thesis_experiment_outcome{{{},kind=\"experimental_and_compare\",outcome=\"mismatch\"}} 1\n",
+            name
+        )));
+
+        assert!(text.contains("# TYPE thesis_experiment_run_duration histogram\n"));
+        assert!(text.contains(&format!(
+            "thesis_experiment_run_duration_bucket{{{},kind=\"control\",le=\"0.025\"}} 2\n",
+            name
+        )));
+        assert!(text.contains(&format!(
+            "thesis_experiment_run_duration_bucket{{{},kind=\"control\",le=\"+Inf\"}} 3\n",
+            name
+        )));
+        assert!(text.contains(&format!(
+            "thesis_experiment_run_duration_sum{{{},kind=\"control\"}} 5.03\n",
+            name
+        )));
+        assert!(text.contains(&format!(
+            "thesis_experiment_run_duration_count{{{},kind=\"control\"}} 3\n",
+            name
+        )));
+    }
+
+    #[test]
+    fn it_follows_the_metrics_config() {
+        let config = MetricsConfig::new()
+            .prefix("payments")
+            .rename("thesis_experiment_run_total", "thesis_experiment_runs")
+            .duration_unit(DurationUnit::Milliseconds);
+        let text = render_with_config(&snapshot(), &config);
+
+        assert!(text.contains("# TYPE payments_thesis_experiment_runs counter\n"));
+        assert!(text.contains("# TYPE payments_thesis_experiment_outcome counter\n"));
+        assert!(text.contains("le=\"25\"} 2\n"));
+        assert!(text.contains("payments_thesis_experiment_run_duration_sum{"));
+        assert!(text.contains("} 5030\n"));
+    }
+
+    #[cfg(feature = "prometheus-server")]
+    #[test]
+    fn it_serves_the_rendered_statistics() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let addr = serve("127.0.0.1:0").unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE thesis_experiment_run_total counter\n"));
+
+        // An idle client is disconnected rather than blocking later requests
+        let _idle = TcpStream::connect(addr).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
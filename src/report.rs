@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use crate::rollout::RolloutDecision;
+
+/// The outcome of a single experiment run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+    /// Both methods ran and returned the same value
+    Match,
+
+    /// Both methods ran and returned different values. With `run_result`, this
+    /// includes one method returning `Ok` while the other returned `Err`.
+    Mismatch,
+
+    /// Every method which ran returned `Err`. Only produced by `run_result`.
+    Error,
+
+    /// Only one method ran, so no comparison was made. With `run_result`, the
+    /// method returned `Ok`.
+    Ignored,
+
+    /// The experimental method failed, so the control method was run instead.
+    /// Only produced by `RolloutDecision::UseExperimentalWithFallback`.
+    Fallback,
+}
+
+/// Where the value returned to the caller came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Returned {
+    /// The value returned by the control method
+    Control,
+
+    /// The value returned by the experimental method
+    Experimental,
+
+    /// The value returned by the `on_mismatch` handler
+    MismatchHandler,
+}
+
+/// A summary of a single experiment run, returned alongside the value by
+/// `Experiment::run_with_report` and `Experiment::run_result_with_report`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Report {
+    /// The decision made by the rollout strategy
+    pub decision: RolloutDecision,
+
+    /// How long the control method took, if it ran
+    pub control_duration: Option<Duration>,
+
+    /// How long the experimental method took, if it ran
+    pub experimental_duration: Option<Duration>,
+
+    /// The outcome of comparing the two methods, or `Outcome::Fallback` if the
+    /// control was run because the experimental failed
+    pub outcome: Outcome,
+
+    /// Which value was returned to the caller
+    pub returned: Returned,
+}
@@ -1,7 +1,18 @@
-use rand::Rng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::latency;
+use crate::Error;
 
 /// A decision of if the control or experimental methods should be used
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RolloutDecision {
     /// Run only the control method
     UseControl,
@@ -12,6 +23,24 @@ pub enum RolloutDecision {
 
     // Run only the experimental method
     UseExperimental,
+
+    /// Run only the experimental method, unless it panics, or returns `Err`
+    /// when run with `run_result`. The control method is then run instead and
+    /// its result is returned.
+    UseExperimentalWithFallback,
+}
+
+#[cfg(any(feature = "metrics", feature = "opentelemetry"))]
+impl RolloutDecision {
+    /// The value of the `kind` metric label for this decision
+    pub(crate) fn kind(self) -> &'static str {
+        match self {
+            RolloutDecision::UseControl => "control",
+            RolloutDecision::UseExperimentalAndCompare => "experimental_and_compare",
+            RolloutDecision::UseExperimental => "experimental",
+            RolloutDecision::UseExperimentalWithFallback => "experimental_with_fallback",
+        }
+    }
 }
 
 /// A method for chosing if the control or experimental code should run
@@ -25,26 +54,529 @@ impl RolloutStrategy for RolloutDecision {
     }
 }
 
+impl<R> RolloutStrategy for &R
+where
+    R: RolloutStrategy + ?Sized,
+{
+    fn rollout_decision(&self) -> RolloutDecision {
+        (**self).rollout_decision()
+    }
+}
+
+/// Ask the strategy for a decision, unless one is forced by `thesis::testing`.
+/// Public for `#[thesis::experiment]` and `#[thesis::dual]`, which decide
+/// before building the experimental so arguments are only cloned when it runs.
+#[doc(hidden)]
+pub fn decide<R>(name: &'static str, strategy: &R) -> RolloutDecision
+where
+    R: RolloutStrategy,
+{
+    #[cfg(any(test, feature = "testing"))]
+    if let Some(decision) = crate::testing::forced_decision(name) {
+        return decision;
+    }
+
+    #[cfg(not(any(test, feature = "testing")))]
+    let _ = name;
+
+    strategy.rollout_decision()
+}
+
 /// The simplest rollout strategy, a floating point number between 0 and 100 that
 /// represents the percentage of requests which should use the experimental
 /// method. The experimental results will be compared to the control results.
-pub struct Percent(f64);
+///
+/// Decisions are made with the thread-local RNG from `rand`, unless a different
+/// RNG is given with `Percent::with_rng`, a global RNG has been seeded with
+/// `seed_global_rng`, or the current thread's RNG has been seeded with
+/// `seed_thread_rng`, in that order of priority.
+pub struct Percent {
+    /// Stored as given, so `percent` returns exactly what the `Percent` was
+    /// created with
+    percent: f64,
+    rng: Option<Mutex<Box<dyn RngCore + Send>>>,
+}
 
 impl Percent {
-    /// Create a new rollout Percent
+    /// Create a new rollout Percent. `percent` should be between 0 and 100, use
+    /// `Percent::try_new` to handle invalid values.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug builds if `percent` is NaN or not between 0 and 100.
     pub fn new(percent: f64) -> Self {
-        Self(percent / 100.0)
+        debug_assert!(
+            is_valid_percent(percent),
+            "percent must be between 0 and 100, got {}",
+            percent
+        );
+
+        Self { percent, rng: None }
+    }
+
+    /// Create a new rollout Percent, or return `Error::InvalidPercent` if
+    /// `percent` is NaN or not between 0 and 100
+    pub fn try_new(percent: f64) -> Result<Self, Error> {
+        if !is_valid_percent(percent) {
+            return Err(Error::InvalidPercent(percent));
+        }
+
+        Ok(Self::new(percent))
+    }
+
+    /// Create a new rollout Percent from a number of basis points, hundredths
+    /// of a percent, or return `Error::InvalidBasisPoints` if it is over 10000
+    pub fn from_basis_points(basis_points: u32) -> Result<Self, Error> {
+        if basis_points > 10_000 {
+            return Err(Error::InvalidBasisPoints(basis_points));
+        }
+
+        Ok(Self::new(f64::from(basis_points) / 100.0))
+    }
+
+    /// The percentage of requests which use the experimental method, between 0
+    /// and 100
+    pub fn percent(&self) -> f64 {
+        self.percent
+    }
+
+    /// The fraction of requests which use the experimental method, between 0
+    /// and 1
+    pub fn fraction(&self) -> f64 {
+        self.percent / 100.0
+    }
+
+    /// Make rollout decisions with the given RNG instead of the thread-local
+    /// RNG. Using a seeded RNG makes the sequence of decisions reproducible.
+    pub fn with_rng<G>(self, rng: G) -> Self
+    where
+        G: RngCore + Send + 'static,
+    {
+        Self {
+            rng: Some(Mutex::new(Box::new(rng))),
+            ..self
+        }
+    }
+
+    fn sample(&self) -> f64 {
+        if let Some(rng) = &self.rng {
+            return rng
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .gen();
+        }
+
+        if GLOBAL_RNG_SEEDED.load(Ordering::Acquire) {
+            if let Some(rng) = &mut *GLOBAL_RNG
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+            {
+                return rng.gen();
+            }
+        }
+
+        SEEDED_RNG.with(|seeded| match &mut *seeded.borrow_mut() {
+            Some(rng) => rng.gen(),
+            None => rand::thread_rng().gen(),
+        })
     }
 }
 
+impl fmt::Debug for Percent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Percent")
+            .field("percent", &self.percent())
+            .finish_non_exhaustive()
+    }
+}
+
+fn is_valid_percent(percent: f64) -> bool {
+    (0.0..=100.0).contains(&percent)
+}
+
 impl RolloutStrategy for Percent {
     fn rollout_decision(&self) -> RolloutDecision {
-        let mut rng = rand::thread_rng();
-
-        if rng.gen::<f64>() < self.0 {
+        if self.sample() < self.fraction() {
             RolloutDecision::UseExperimentalAndCompare
         } else {
             RolloutDecision::UseControl
         }
     }
 }
+
+/// Serialized as the percentage it was created with. Any RNG given with
+/// `Percent::with_rng` is not serialized.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Percent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_f64(self.percent())
+    }
+}
+
+/// Deserialized from a percentage, which must be between 0 and 100
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Percent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let percent = f64::deserialize(deserializer)?;
+        Percent::try_new(percent).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Set while `GLOBAL_RNG` is seeded, so unseeded decisions don't take the lock
+static GLOBAL_RNG_SEEDED: AtomicBool = AtomicBool::new(false);
+static GLOBAL_RNG: Mutex<Option<ChaCha8Rng>> = Mutex::new(None);
+
+/// Make every `Percent` without its own RNG use a single RNG seeded with `seed`
+/// for decisions made on any thread, until `reset_global_rng` is called. Takes
+/// priority over `seed_thread_rng`. The RNG is `rand_chacha`'s `ChaCha8Rng`,
+/// whose output for a given seed doesn't change between versions. Intended for simulations and integration
+/// tests which need reproducible rollouts on a multi-threaded runtime. Which
+/// experiment gets which decision still depends on the order threads make them
+/// in.
+pub fn seed_global_rng(seed: u64) {
+    *GLOBAL_RNG
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(ChaCha8Rng::seed_from_u64(seed));
+    GLOBAL_RNG_SEEDED.store(true, Ordering::Release);
+}
+
+/// Undo `seed_global_rng`
+pub fn reset_global_rng() {
+    GLOBAL_RNG_SEEDED.store(false, Ordering::Release);
+    *GLOBAL_RNG
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+}
+
+thread_local! {
+    static SEEDED_RNG: RefCell<Option<ChaCha8Rng>> = const { RefCell::new(None) };
+}
+
+/// Make every `Percent` without its own RNG use an RNG seeded with `seed` for
+/// decisions made on the current thread, until `reset_thread_rng` is called.
+/// Intended for tests and simulations which need reproducible rollouts. Like
+/// `seed_global_rng`, this uses `ChaCha8Rng`.
+pub fn seed_thread_rng(seed: u64) {
+    SEEDED_RNG.with(|seeded| *seeded.borrow_mut() = Some(ChaCha8Rng::seed_from_u64(seed)));
+}
+
+/// Undo `seed_thread_rng`, going back to the thread-local RNG from `rand` for
+/// decisions made on the current thread
+pub fn reset_thread_rng() {
+    SEEDED_RNG.with(|seeded| *seeded.borrow_mut() = None);
+}
+
+/// A rollout strategy which wraps another, and only runs the control while the
+/// experimental is too slow. Every second at most, it compares the p95
+/// durations from `thesis::latency` of the experiment with the given name. If
+/// the experimental p95 is more than `max_ratio` times the control p95, every
+/// decision is `RolloutDecision::UseControl` until the cooldown has passed. The
+/// wrapped strategy is then used again, and the guard only looks at samples
+/// recorded after the cooldown. The samples themselves are kept, so
+/// `latency::summary` still includes them.
+pub struct LatencyGuard<R> {
+    name: &'static str,
+    inner: R,
+    max_ratio: f64,
+    min_samples: usize,
+    cooldown: Duration,
+
+    /// When the guard was created. The atomics below are nanoseconds since
+    /// then, so most decisions can be made without locking `state`.
+    created_at: Instant,
+
+    /// When the cooldown ends, if the guard has tripped
+    tripped_until: AtomicU64,
+
+    /// When latencies are next due to be compared
+    next_check: AtomicU64,
+
+    state: Mutex<GuardState>,
+}
+
+/// The state of a `LatencyGuard` which is only needed when comparing latencies
+#[derive(Default)]
+struct GuardState {
+    tripped: bool,
+
+    /// When the last cooldown ended. Samples from before then are ignored.
+    recovered_at: Option<Instant>,
+}
+
+impl<R> LatencyGuard<R> {
+    /// Guard the experiment with the given name, using `inner` while its
+    /// experimental p95 is at most `max_ratio` times its control p95. By
+    /// default, latencies are only compared once 100 runs have compared both
+    /// methods, and the cooldown is 5 minutes.
+    pub fn new(name: &'static str, inner: R, max_ratio: f64) -> Self {
+        Self {
+            name,
+            inner,
+            max_ratio,
+            min_samples: 100,
+            cooldown: Duration::from_secs(300),
+            created_at: Instant::now(),
+            tripped_until: AtomicU64::new(0),
+            next_check: AtomicU64::new(0),
+            state: Mutex::new(GuardState::default()),
+        }
+    }
+
+    /// Only compare latencies once this many runs have compared both methods
+    pub fn min_samples(self, min_samples: usize) -> Self {
+        Self {
+            min_samples,
+            ..self
+        }
+    }
+
+    /// Keep using the control for this long after the experimental was found
+    /// to be too slow
+    pub fn cooldown(self, cooldown: Duration) -> Self {
+        Self { cooldown, ..self }
+    }
+
+    /// Whether the experimental is currently too slow to be run. The state is
+    /// only locked when latencies are due to be compared, at most once every
+    /// `CHECK_INTERVAL`.
+    fn is_tripped(&self) -> bool {
+        let now = Instant::now();
+        let elapsed = nanos(now.duration_since(self.created_at));
+
+        if elapsed < self.tripped_until.load(Ordering::Acquire) {
+            return true;
+        }
+        if elapsed < self.next_check.load(Ordering::Acquire) {
+            return false;
+        }
+
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        // Another thread may have compared latencies while this one waited
+        if elapsed < self.next_check.load(Ordering::Acquire) {
+            return elapsed < self.tripped_until.load(Ordering::Acquire);
+        }
+        self.next_check.store(
+            elapsed.saturating_add(nanos(CHECK_INTERVAL)),
+            Ordering::Release,
+        );
+
+        if state.tripped {
+            state.tripped = false;
+            state.recovered_at = Some(now);
+        }
+
+        let summary = match state.recovered_at {
+            Some(recovered_at) => latency::summary_since(self.name, recovered_at),
+            None => latency::summary(self.name),
+        };
+        let summary = match summary {
+            Some(summary) if summary.samples >= self.min_samples => summary,
+            _ => return false,
+        };
+
+        let control = summary.control.p95.as_secs_f64();
+        let experimental = summary.experimental.p95.as_secs_f64();
+        if experimental <= control * self.max_ratio {
+            return false;
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            name = self.name,
+            control_p95 = ?summary.control.p95,
+            experimental_p95 = ?summary.experimental.p95,
+            cooldown = ?self.cooldown,
+            "thesis experiment is too slow, using the control until the cooldown has passed"
+        );
+
+        // Nothing needs comparing until the cooldown has passed
+        let tripped_until = elapsed.saturating_add(nanos(self.cooldown));
+        self.tripped_until.store(tripped_until, Ordering::Release);
+        self.next_check.store(tripped_until, Ordering::Release);
+        state.tripped = true;
+        true
+    }
+}
+
+/// The duration in nanoseconds, saturating at `u64::MAX`
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// How often `LatencyGuard` compares latencies
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+impl<R> RolloutStrategy for LatencyGuard<R>
+where
+    R: RolloutStrategy,
+{
+    fn rollout_decision(&self) -> RolloutDecision {
+        if self.is_tripped() {
+            RolloutDecision::UseControl
+        } else {
+            self.inner.rollout_decision()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Held by tests which seed the global or thread RNGs, since the global RNG
+    /// takes priority over the thread RNG
+    static SEEDED_RNG_TESTS: Mutex<()> = Mutex::new(());
+
+    fn decisions(percent: &Percent) -> Vec<RolloutDecision> {
+        (0..100).map(|_| percent.rollout_decision()).collect()
+    }
+
+    #[test]
+    fn it_is_reproducible_with_a_seeded_rng() {
+        let first = Percent::new(50.0).with_rng(ChaCha8Rng::seed_from_u64(42));
+        let second = Percent::new(50.0).with_rng(ChaCha8Rng::seed_from_u64(42));
+
+        assert_eq!(decisions(&first), decisions(&second));
+    }
+
+    #[test]
+    fn it_is_reproducible_with_a_seeded_thread_rng() {
+        let _lock = SEEDED_RNG_TESTS.lock();
+        let percent = Percent::new(50.0);
+
+        seed_thread_rng(7);
+        let first = decisions(&percent);
+
+        seed_thread_rng(7);
+        let second = decisions(&percent);
+
+        reset_thread_rng();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn it_is_reproducible_with_a_seeded_global_rng() {
+        let _lock = SEEDED_RNG_TESTS.lock();
+        let percent = Percent::new(50.0);
+
+        seed_global_rng(7);
+        let first = std::thread::spawn(move || decisions(&percent))
+            .join()
+            .unwrap();
+
+        seed_global_rng(7);
+        seed_thread_rng(8);
+        let second = decisions(&Percent::new(50.0));
+
+        reset_global_rng();
+        reset_thread_rng();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn it_validates_percents() {
+        assert_eq!(Percent::try_new(12.5).unwrap().percent(), 12.5);
+        assert_eq!(Percent::new(7.0).percent(), 7.0);
+        assert_eq!(Percent::new(7.0).fraction(), 0.07);
+        assert_eq!(Percent::try_new(100.0).unwrap().fraction(), 1.0);
+
+        assert_eq!(
+            Percent::try_new(500.0).unwrap_err(),
+            Error::InvalidPercent(500.0)
+        );
+        assert!(Percent::try_new(-1.0).is_err());
+        assert!(Percent::try_new(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn it_creates_percents_from_basis_points() {
+        assert_eq!(Percent::from_basis_points(125).unwrap().percent(), 1.25);
+        assert_eq!(Percent::from_basis_points(5_700).unwrap().percent(), 57.0);
+        assert_eq!(Percent::from_basis_points(10_000).unwrap().fraction(), 1.0);
+        assert_eq!(
+            Percent::from_basis_points(10_001).unwrap_err(),
+            Error::InvalidBasisPoints(10_001)
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "percent must be between 0 and 100, got 500")]
+    fn it_rejects_invalid_percents_in_debug_builds() {
+        Percent::new(500.0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_validates_deserialized_percents() {
+        let percent: Percent = serde_json::from_str("12.5").unwrap();
+        assert_eq!(serde_json::to_string(&percent).unwrap(), "12.5");
+
+        assert!(serde_json::from_str::<Percent>("-1").is_err());
+        assert!(serde_json::from_str::<Percent>("100.5").is_err());
+
+        for percent in ["7.0", "57.0"] {
+            let round_tripped: Percent = serde_json::from_str(percent).unwrap();
+            assert_eq!(serde_json::to_string(&round_tripped).unwrap(), percent);
+        }
+    }
+
+    #[test]
+    fn it_uses_the_control_while_the_experimental_is_too_slow() {
+        for _ in 0..10 {
+            latency::record(
+                "guarded",
+                Duration::from_millis(10),
+                Duration::from_millis(20),
+            );
+        }
+
+        let guard = LatencyGuard::new("guarded", RolloutDecision::UseExperimentalAndCompare, 1.5)
+            .min_samples(10)
+            .cooldown(Duration::ZERO);
+
+        assert_eq!(guard.rollout_decision(), RolloutDecision::UseControl);
+
+        // The cooldown has passed, so the slow samples are ignored by the guard
+        // but still summarized
+        assert_eq!(
+            guard.rollout_decision(),
+            RolloutDecision::UseExperimentalAndCompare
+        );
+        assert_eq!(latency::summary("guarded").unwrap().samples, 10);
+    }
+
+    #[test]
+    fn it_keeps_using_the_control_until_the_cooldown_has_passed() {
+        for _ in 0..10 {
+            latency::record(
+                "guarded until cooldown",
+                Duration::from_millis(10),
+                Duration::from_millis(20),
+            );
+        }
+
+        let guard = LatencyGuard::new(
+            "guarded until cooldown",
+            RolloutDecision::UseExperimentalAndCompare,
+            1.5,
+        )
+        .min_samples(10);
+
+        for _ in 0..10 {
+            assert_eq!(guard.rollout_decision(), RolloutDecision::UseControl);
+        }
+    }
+}
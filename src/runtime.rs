@@ -0,0 +1,258 @@
+//! Thesis doesn't depend on any particular async runtime. Features which need
+//! to spawn tasks or wait for a timer do so through the `Spawner` and `Timer`
+//! traits defined here. Implementations for tokio, async-std and smol are
+//! provided behind the `tokio`, `async-std` and `smol` cargo features.
+
+use futures::future::BoxFuture;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Spawns work onto an async runtime
+pub trait Spawner: Send + Sync {
+    /// Run the given future in the background, without waiting for it to
+    /// finish
+    fn spawn(&self, future: BoxFuture<'static, ()>);
+
+    /// Run the given function on a thread where blocking is allowed, without
+    /// waiting for it to finish
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>);
+}
+
+/// Creates timers on an async runtime
+pub trait Timer: Send + Sync {
+    /// Create a future which completes once the given duration has passed
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Runs work on the tokio runtime. Spawning and sleeping panic if called from
+/// outside of a tokio runtime, so `global_spawner` and `global_timer` only pick
+/// it by default from inside one. Sleeping also panics if the runtime was built
+/// without its time driver enabled.
+#[cfg(feature = "tokio")]
+pub struct Tokio;
+
+#[cfg(feature = "tokio")]
+impl Spawner for Tokio {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        tokio::spawn(future);
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        tokio::task::spawn_blocking(f);
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Timer for Tokio {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Runs work on the async-std runtime
+#[cfg(feature = "async-std")]
+pub struct AsyncStd;
+
+#[cfg(feature = "async-std")]
+impl Spawner for AsyncStd {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        async_std::task::spawn(future);
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        async_std::task::spawn_blocking(f);
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl Timer for AsyncStd {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async_std::task::sleep(duration))
+    }
+}
+
+/// Runs work on smol's global executor and blocking thread pool
+#[cfg(feature = "smol")]
+pub struct Smol;
+
+#[cfg(feature = "smol")]
+impl Spawner for Smol {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        smol::spawn(future).detach();
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        smol::spawn(smol::unblock(f)).detach();
+    }
+}
+
+#[cfg(feature = "smol")]
+impl Timer for Smol {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            smol::Timer::after(duration).await;
+        })
+    }
+}
+
+static GLOBAL_SPAWNER: RwLock<Option<Arc<dyn Spawner>>> = RwLock::new(None);
+static GLOBAL_TIMER: RwLock<Option<Arc<dyn Timer>>> = RwLock::new(None);
+
+/// Use the given spawner for every thesis feature which needs to spawn work.
+/// Replaces any previously set spawner.
+pub fn set_global_spawner<S>(spawner: S)
+where
+    S: Spawner + 'static,
+{
+    *GLOBAL_SPAWNER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(spawner));
+}
+
+/// Use the given timer for every thesis feature which needs to wait. Replaces
+/// any previously set timer.
+pub fn set_global_timer<T>(timer: T)
+where
+    T: Timer + 'static,
+{
+    *GLOBAL_TIMER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(timer));
+}
+
+/// The spawner set with `set_global_spawner`. If none was set, this is `Tokio`
+/// when called from inside a tokio runtime with the `tokio` feature enabled,
+/// and otherwise the spawner for the first enabled runtime feature out of
+/// `async-std` and `smol`, or `None` if neither of them are enabled.
+///
+/// Cargo features are shared by every crate in a build, so another dependency
+/// may enable a runtime feature the application doesn't use. Applications which
+/// don't run on tokio should call `set_global_spawner` to be sure the right
+/// runtime is used.
+pub fn global_spawner() -> Option<Arc<dyn Spawner>> {
+    let spawner = GLOBAL_SPAWNER
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+
+    spawner.or_else(default_spawner)
+}
+
+/// The timer set with `set_global_timer`. If none was set, this is picked the
+/// same way as `global_spawner`.
+pub fn global_timer() -> Option<Arc<dyn Timer>> {
+    let timer = GLOBAL_TIMER
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+
+    timer.or_else(default_timer)
+}
+
+fn default_spawner() -> Option<Arc<dyn Spawner>> {
+    #[cfg(feature = "tokio")]
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Some(Arc::new(Tokio));
+    }
+
+    fallback_spawner()
+}
+
+/// The spawner to use outside of a tokio runtime. async-std and smol run their
+/// own executors, so they can spawn from anywhere.
+#[cfg(feature = "async-std")]
+fn fallback_spawner() -> Option<Arc<dyn Spawner>> {
+    Some(Arc::new(AsyncStd))
+}
+
+#[cfg(all(not(feature = "async-std"), feature = "smol"))]
+fn fallback_spawner() -> Option<Arc<dyn Spawner>> {
+    Some(Arc::new(Smol))
+}
+
+#[cfg(not(any(feature = "async-std", feature = "smol")))]
+fn fallback_spawner() -> Option<Arc<dyn Spawner>> {
+    None
+}
+
+fn default_timer() -> Option<Arc<dyn Timer>> {
+    #[cfg(feature = "tokio")]
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Some(Arc::new(Tokio));
+    }
+
+    fallback_timer()
+}
+
+/// The timer to use outside of a tokio runtime
+#[cfg(feature = "async-std")]
+fn fallback_timer() -> Option<Arc<dyn Timer>> {
+    Some(Arc::new(AsyncStd))
+}
+
+#[cfg(all(not(feature = "async-std"), feature = "smol"))]
+fn fallback_timer() -> Option<Arc<dyn Timer>> {
+    Some(Arc::new(Smol))
+}
+
+#[cfg(not(any(feature = "async-std", feature = "smol")))]
+fn fallback_timer() -> Option<Arc<dyn Timer>> {
+    None
+}
+
+#[cfg(all(test, any(feature = "tokio", feature = "async-std", feature = "smol")))]
+mod tests {
+    use super::*;
+
+    async fn spawn_and_sleep<R>(runtime: R)
+    where
+        R: Spawner + Timer,
+    {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        runtime.spawn(Box::pin(async move {
+            tx.send(1).unwrap();
+        }));
+        assert_eq!(rx.await, Ok(1));
+
+        let (tx, rx) = futures::channel::oneshot::channel();
+        runtime.spawn_blocking(Box::new(move || {
+            tx.send(2).unwrap();
+        }));
+        assert_eq!(rx.await, Ok(2));
+
+        runtime.sleep(Duration::from_millis(1)).await;
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn it_runs_on_tokio() {
+        spawn_and_sleep(Tokio).await;
+    }
+
+    #[cfg(all(feature = "tokio", not(any(feature = "async-std", feature = "smol"))))]
+    #[test]
+    fn it_only_picks_tokio_inside_a_tokio_runtime() {
+        assert!(default_spawner().is_none());
+        assert!(default_timer().is_none());
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            assert!(default_spawner().is_some());
+            assert!(default_timer().is_some());
+        });
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn it_runs_on_async_std() {
+        async_std::task::block_on(spawn_and_sleep(AsyncStd));
+    }
+
+    #[cfg(feature = "smol")]
+    #[test]
+    fn it_runs_on_smol() {
+        smol::block_on(spawn_and_sleep(Smol));
+    }
+}
@@ -0,0 +1,380 @@
+//! In-process statistics for every experiment. Unlike the metrics reported by
+//! the default observer, these can be read back with `snapshot`, for example
+//! to serve them from an admin endpoint or to make automated decisions. They
+//! are collected for every experiment, whichever observer it reports to.
+//!
+//! ```
+//! use thesis::{stats, Experiment, RolloutDecision};
+//!
+//! # tokio_test::block_on(async {
+//! Experiment::new("load_data_from_db => load_data_from_redis")
+//!     .control(async { 4 })
+//!     .experimental(async { 5 })
+//!     .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+//!     .run()
+//!     .await;
+//!
+//! let stats = stats::experiment("load_data_from_db => load_data_from_redis").unwrap();
+//! assert_eq!(stats.runs, 1);
+//! assert_eq!(stats.mismatches, 1);
+//! # });
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::latency::{self, LatencySummary};
+use crate::observer::{BranchOutcome, ExperimentObserver, Scope};
+use crate::report::{Outcome, Report};
+use crate::rollout::RolloutDecision;
+
+/// Upper bounds, in seconds, of the buckets of the duration histograms
+pub const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Statistics of every experiment which has run, by name
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub experiments: BTreeMap<String, ExperimentStats>,
+}
+
+/// Statistics of a single experiment since the process started
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExperimentStats {
+    /// How many times the experiment was run
+    pub runs: u64,
+
+    /// How many runs made each rollout decision
+    pub decisions: Decisions,
+
+    /// How many times the experimental was skipped even though the rollout
+    /// strategy asked for it
+    pub skipped: u64,
+
+    /// How many runs compared both methods and found them equal
+    pub matches: u64,
+
+    /// How many runs compared both methods and found them different
+    pub mismatches: u64,
+
+    /// How many runs fell back to the control after the experimental failed
+    pub fallbacks: u64,
+
+    /// Outcomes and durations of the control method
+    pub control: BranchStats,
+
+    /// Outcomes and durations of the experimental method
+    pub experimental: BranchStats,
+
+    /// Percentiles of the most recent paired durations, see `thesis::latency`
+    pub latency: Option<LatencySummary>,
+}
+
+/// Number of runs which made each rollout decision
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Decisions {
+    pub control: u64,
+    pub experimental: u64,
+    pub experimental_and_compare: u64,
+    pub experimental_with_fallback: u64,
+}
+
+/// Statistics of the control or experimental method of an experiment
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BranchStats {
+    /// How many times the method returned `Ok`. Only counted by `run_result`.
+    pub ok: u64,
+
+    /// How many times the method returned `Err`. Only counted by `run_result`.
+    pub errors: u64,
+
+    /// How long the method took
+    pub duration: Histogram,
+}
+
+/// A histogram of durations, with the buckets in `DURATION_BUCKETS`
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Histogram {
+    /// For each bucket in `DURATION_BUCKETS`, how many durations were at most
+    /// its upper bound
+    pub buckets: Vec<u64>,
+
+    /// How many durations were recorded
+    pub count: u64,
+
+    /// The sum of every recorded duration
+    pub sum: Duration,
+}
+
+/// Statistics of every experiment which has run
+pub fn snapshot() -> Snapshot {
+    let registry = REGISTRY
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let experiments = registry
+        .iter()
+        .flatten()
+        .map(|(name, counters)| (name.to_string(), counters.snapshot(name)))
+        .collect();
+
+    Snapshot { experiments }
+}
+
+/// Statistics of the named experiment, or `None` if it hasn't run
+pub fn experiment(name: &str) -> Option<ExperimentStats> {
+    REGISTRY
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_ref()?
+        .get(name)
+        .map(|counters| counters.snapshot(name))
+}
+
+/// Counters of every experiment which has run, by name
+type Registry = HashMap<&'static str, Arc<Counters>>;
+
+static REGISTRY: RwLock<Option<Registry>> = RwLock::new(None);
+
+thread_local! {
+    /// The counters each thread has looked up so far, so runs only take the
+    /// registry lock the first time an experiment runs on a thread
+    static CACHE: RefCell<HashMap<&'static str, Arc<Counters>>> = RefCell::new(HashMap::new());
+}
+
+fn counters(name: &'static str) -> Arc<Counters> {
+    let cached = CACHE
+        .try_with(|cache| cache.borrow().get(name).cloned())
+        .ok()
+        .flatten();
+    if let Some(counters) = cached {
+        return counters;
+    }
+
+    let existing = REGISTRY
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_ref()
+        .and_then(|registry| registry.get(name).cloned());
+
+    let counters = existing.unwrap_or_else(|| {
+        REGISTRY
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get_or_insert_with(HashMap::new)
+            .entry(name)
+            .or_default()
+            .clone()
+    });
+
+    let _ = CACHE.try_with(|cache| cache.borrow_mut().insert(name, counters.clone()));
+    counters
+}
+
+#[derive(Default)]
+struct Counters {
+    runs: AtomicU64,
+    decisions: [AtomicU64; 4],
+    skipped: AtomicU64,
+    matches: AtomicU64,
+    mismatches: AtomicU64,
+    fallbacks: AtomicU64,
+    control: BranchCounters,
+    experimental: BranchCounters,
+}
+
+#[derive(Default)]
+struct BranchCounters {
+    ok: AtomicU64,
+    errors: AtomicU64,
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+impl Counters {
+    fn branch(&self, kind: &str) -> Option<&BranchCounters> {
+        match kind {
+            "control" => Some(&self.control),
+            "experimental" => Some(&self.experimental),
+            _ => None,
+        }
+    }
+
+    fn snapshot(&self, name: &str) -> ExperimentStats {
+        ExperimentStats {
+            runs: load(&self.runs),
+            decisions: Decisions {
+                control: load(&self.decisions[0]),
+                experimental: load(&self.decisions[1]),
+                experimental_and_compare: load(&self.decisions[2]),
+                experimental_with_fallback: load(&self.decisions[3]),
+            },
+            skipped: load(&self.skipped),
+            matches: load(&self.matches),
+            mismatches: load(&self.mismatches),
+            fallbacks: load(&self.fallbacks),
+            control: self.control.snapshot(),
+            experimental: self.experimental.snapshot(),
+            latency: latency::summary(name),
+        }
+    }
+}
+
+impl BranchCounters {
+    fn record_duration(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, upper_bound) in self.buckets.iter().zip(DURATION_BUCKETS) {
+            if seconds <= upper_bound {
+                increment(bucket);
+            }
+        }
+
+        increment(&self.count);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> BranchStats {
+        BranchStats {
+            ok: load(&self.ok),
+            errors: load(&self.errors),
+            duration: Histogram {
+                buckets: self.buckets.iter().map(load).collect(),
+                count: load(&self.count),
+                sum: Duration::from_nanos(load(&self.sum_nanos)),
+            },
+        }
+    }
+}
+
+/// Wrap the observer of a single run of the named experiment, so the run is
+/// counted before the observer is notified
+pub(crate) fn observe(name: &'static str, inner: Arc<dyn ExperimentObserver>) -> StatsObserver {
+    StatsObserver {
+        counters: counters(name),
+        inner,
+    }
+}
+
+/// The observer every run reports to, which counts the run and passes
+/// everything on to the observer of the experiment
+#[derive(Clone)]
+pub(crate) struct StatsObserver {
+    counters: Arc<Counters>,
+    inner: Arc<dyn ExperimentObserver>,
+}
+
+impl ExperimentObserver for StatsObserver {
+    fn on_run_start(&self, name: &'static str) {
+        increment(&self.counters.runs);
+        self.inner.on_run_start(name);
+    }
+
+    fn run_scope(&self, name: &'static str) -> Option<Arc<dyn Scope>> {
+        self.inner.run_scope(name)
+    }
+
+    fn branch_scope(&self, name: &'static str, kind: &'static str) -> Option<Arc<dyn Scope>> {
+        self.inner.branch_scope(name, kind)
+    }
+
+    fn on_decision(&self, name: &'static str, decision: RolloutDecision) {
+        let index = match decision {
+            RolloutDecision::UseControl => 0,
+            RolloutDecision::UseExperimental => 1,
+            RolloutDecision::UseExperimentalAndCompare => 2,
+            RolloutDecision::UseExperimentalWithFallback => 3,
+        };
+        increment(&self.counters.decisions[index]);
+        self.inner.on_decision(name, decision);
+    }
+
+    fn on_skipped(&self, name: &'static str, reason: &'static str) {
+        increment(&self.counters.skipped);
+        self.inner.on_skipped(name, reason);
+    }
+
+    fn on_branch_duration(&self, name: &'static str, kind: &'static str, duration: Duration) {
+        if let Some(branch) = self.counters.branch(kind) {
+            branch.record_duration(duration);
+        }
+        self.inner.on_branch_duration(name, kind, duration);
+    }
+
+    fn on_outcome(&self, name: &'static str, kind: &'static str, outcome: BranchOutcome<'_>) {
+        if let Some(branch) = self.counters.branch(kind) {
+            match outcome {
+                BranchOutcome::Ok => increment(&branch.ok),
+                BranchOutcome::Error { .. } => increment(&branch.errors),
+            }
+        }
+        self.inner.on_outcome(name, kind, outcome);
+    }
+
+    fn on_fallback(&self, name: &'static str, reason: &dyn Display) {
+        increment(&self.counters.fallbacks);
+        self.inner.on_fallback(name, reason);
+    }
+
+    fn on_latency_delta(&self, name: &'static str, control: Duration, experimental: Duration) {
+        self.inner.on_latency_delta(name, control, experimental);
+    }
+
+    fn on_mismatch(&self, name: &'static str, category: Option<&'static str>) {
+        increment(&self.counters.mismatches);
+        self.inner.on_mismatch(name, category);
+    }
+
+    fn on_race_won(&self, name: &'static str, kind: &'static str) {
+        self.inner.on_race_won(name, kind);
+    }
+
+    fn on_run_end(&self, name: &'static str, report: &Report) {
+        if report.outcome == Outcome::Match {
+            increment(&self.counters.matches);
+        }
+        self.inner.on_run_end(name, report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Quiet;
+
+    impl ExperimentObserver for Quiet {}
+
+    #[test]
+    fn it_counts_durations_in_cumulative_buckets() {
+        let observer = observe("bucketed", Arc::new(Quiet));
+        observer.on_branch_duration("bucketed", "control", Duration::from_millis(3));
+        observer.on_branch_duration("bucketed", "control", Duration::from_millis(200));
+        observer.on_branch_duration("bucketed", "control", Duration::from_secs(60));
+
+        let duration = experiment("bucketed").unwrap().control.duration;
+        assert_eq!(duration.buckets[0], 1);
+        assert_eq!(duration.buckets[5], 2);
+        assert_eq!(duration.buckets[10], 2);
+        assert_eq!(duration.count, 3);
+        assert_eq!(duration.sum, Duration::from_millis(60_203));
+    }
+}
@@ -0,0 +1,563 @@
+use futures::stream::{self, Stream, StreamExt};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::latency;
+use crate::metadata::{self, Metadata};
+use crate::mismatch::FnTrait;
+use crate::observer::{self, ExperimentObserver, Scope};
+use crate::report::{Outcome, Report, Returned};
+use crate::rollout::{self, RolloutDecision, RolloutStrategy};
+use crate::stats::StatsObserver;
+use crate::trace::{info_span, Instrument, Span};
+
+#[derive(Debug)]
+/// Type passed to the `on_mismatch` function of a `StreamExperiment`, which is
+/// called when the control and experimental streams yield different items or a
+/// different number of items.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamMismatch {
+    /// Index of the first item where the control and experimental streams
+    /// yielded different values. `None` if every item both streams yielded was
+    /// equal, which means only the lengths differ.
+    pub first_diverging_index: Option<usize>,
+
+    /// The number of items yielded by the control stream
+    pub control_len: usize,
+
+    /// The number of items yielded by the experimental stream. The experimental
+    /// stream is dropped once the control stream ends, so if it was longer than
+    /// the control stream this is a lower bound.
+    pub experimental_len: usize,
+
+    /// Time between the first poll of the stream and the control stream ending
+    pub control_duration: Duration,
+
+    /// Time between the first poll of the stream and the experimental stream
+    /// ending. `None` if the experimental stream was longer than the control
+    /// stream, and was dropped before it ended.
+    pub experimental_duration: Option<Duration>,
+}
+
+/// A `StreamMismatchHandler` is notified when the control and experimental
+/// streams differ. All control items have already been yielded to the caller by
+/// the time it is called, so unlike `MismatchHandler` it does not choose a
+/// value, and is only useful for logging or recording the difference.
+pub trait StreamMismatchHandler {
+    fn on_mismatch(self, mismatch: StreamMismatch);
+}
+
+/// A stream mismatch handler which does nothing
+pub struct Ignore;
+
+impl StreamMismatchHandler for Ignore {
+    fn on_mismatch(self, _mismatch: StreamMismatch) {}
+}
+
+impl<F> StreamMismatchHandler for FnTrait<F>
+where
+    F: FnOnce(StreamMismatch),
+{
+    fn on_mismatch(self, mismatch: StreamMismatch) {
+        self.0(mismatch)
+    }
+}
+
+/// An experiment which compares the items of two streams. The items of the
+/// control stream are passed on to the caller, while the experimental stream is
+/// consumed alongside it and compared item by item.
+///
+/// Each control item is only passed on once the experimental stream has yielded
+/// its item at the same index as well, so a slow experimental stream slows down
+/// the caller. Once the control stream ends, the returned stream ends too, and
+/// the rest of the experimental stream is dropped without being consumed.
+///
+/// ```
+/// use futures::stream::{self, StreamExt};
+/// use thesis::{stream::StreamExperiment, RolloutDecision};
+///
+/// # tokio_test::block_on(async {
+/// let items = StreamExperiment::new("paginate from redis")
+///     .control(stream::iter(vec![1, 2, 3]))
+///     .experimental(stream::iter(vec![1, 2, 4]))
+///     .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+///     .on_mismatch(|mismatch| {
+///         assert_eq!(mismatch.first_diverging_index, Some(2));
+///     })
+///     .run()
+///     .collect::<Vec<_>>()
+///     .await;
+///
+/// assert_eq!(items, vec![1, 2, 3]);
+/// # });
+/// ```
+pub struct StreamExperiment<T, C, E, R, M> {
+    item_type: PhantomData<T>,
+    control_builder: C,
+    experimental_builder: E,
+    rollout_strategy: R,
+    mismatch_handler: M,
+    observer: Option<Arc<dyn ExperimentObserver>>,
+    name: &'static str,
+}
+
+impl<T> StreamExperiment<T, (), (), (), Ignore> {
+    /// Create a new stream experiment. The only provided default is ignoring
+    /// mismatches. All other builder-style functions must be called before
+    /// `run` can be called.
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            item_type: PhantomData,
+            control_builder: (),
+            experimental_builder: (),
+            mismatch_handler: Ignore,
+            rollout_strategy: (),
+            observer: None,
+        }
+    }
+}
+
+impl<T, C, E, R, M> StreamExperiment<T, C, E, R, M> {
+    /// Use the stream given here as the control, or the existing method for
+    /// producing items
+    pub fn control<NC>(self, control_builder: NC) -> StreamExperiment<T, NC, E, R, M>
+    where
+        NC: Stream<Item = T>,
+    {
+        StreamExperiment {
+            control_builder,
+            name: self.name,
+            experimental_builder: self.experimental_builder,
+            item_type: self.item_type,
+            rollout_strategy: self.rollout_strategy,
+            mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+        }
+    }
+
+    /// Use the stream given here as the experimental, or the new method for
+    /// producing items
+    pub fn experimental<NE>(self, experimental_builder: NE) -> StreamExperiment<T, C, NE, R, M>
+    where
+        NE: Stream<Item = T>,
+    {
+        StreamExperiment {
+            experimental_builder,
+            name: self.name,
+            item_type: self.item_type,
+            control_builder: self.control_builder,
+            rollout_strategy: self.rollout_strategy,
+            mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+        }
+    }
+
+    /// Use the given strategy for rolling out the new code
+    pub fn rollout_strategy<NR>(self, rollout_strategy: NR) -> StreamExperiment<T, C, E, NR, M> {
+        StreamExperiment {
+            rollout_strategy,
+            name: self.name,
+            item_type: self.item_type,
+            control_builder: self.control_builder,
+            experimental_builder: self.experimental_builder,
+            mismatch_handler: self.mismatch_handler,
+            observer: self.observer,
+        }
+    }
+
+    /// Call this function once the control stream has ended if they yielded
+    /// different items. This can only happen when the rollout strategy returns
+    /// `RolloutDecision::UseExperimentalAndCompare`.
+    pub fn on_mismatch<NM>(self, on_mismatch: NM) -> StreamExperiment<T, C, E, R, FnTrait<NM>>
+    where
+        NM: FnOnce(StreamMismatch),
+    {
+        StreamExperiment {
+            mismatch_handler: FnTrait(on_mismatch),
+            name: self.name,
+            rollout_strategy: self.rollout_strategy,
+            item_type: self.item_type,
+            control_builder: self.control_builder,
+            experimental_builder: self.experimental_builder,
+            observer: self.observer,
+        }
+    }
+
+    /// Report this experiment to the given observer instead of the global
+    /// observer. `ExperimentObserver::on_run_end` is called once the control
+    /// stream ends, and not at all if the returned stream is dropped before
+    /// then.
+    pub fn observer(self, observer: Arc<dyn ExperimentObserver>) -> Self {
+        StreamExperiment {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    /// Declare metadata describing this experiment, such as its owner and when
+    /// it expires. See `thesis::metadata`.
+    pub fn metadata(self, metadata: Metadata) -> Self {
+        metadata::declare(self.name, metadata);
+        self
+    }
+
+    /// Run the experiment with the parameters provided. The rollout decision is
+    /// made immediately, while the comparison and metrics reporting happen once
+    /// the returned stream ends. If the returned stream is dropped before it
+    /// ends, no comparison is made.
+    pub fn run(self) -> impl Stream<Item = T>
+    where
+        T: PartialEq,
+        R: RolloutStrategy,
+        M: StreamMismatchHandler,
+        C: Stream<Item = T>,
+        E: Stream<Item = T>,
+    {
+        let observer = observer::for_run(self.name, self.observer, &[]);
+        let metadata = metadata::for_run(self.name);
+        let span = info_span!(
+            "StreamExperiment::run",
+            experiment_name = self.name,
+            owner = metadata.owner,
+            description = metadata.description,
+            ticket = metadata.ticket,
+            expires = metadata.expires_unix_secs(),
+        );
+
+        #[cfg(not(feature = "tracing"))]
+        let _ = metadata;
+        observer.on_run_start(self.name);
+        let scope = observer.run_scope(self.name);
+
+        let decision = rollout::decide(self.name, &self.rollout_strategy);
+        observer.on_decision(self.name, decision);
+
+        let (control, experimental) = match decision {
+            RolloutDecision::UseControl => (Some(self.control_builder), None),
+            RolloutDecision::UseExperimentalAndCompare => {
+                (Some(self.control_builder), Some(self.experimental_builder))
+            }
+            // Items are passed on to the caller as soon as they are yielded,
+            // so there is no falling back to the control stream
+            RolloutDecision::UseExperimental | RolloutDecision::UseExperimentalWithFallback => {
+                (None, Some(self.experimental_builder))
+            }
+        };
+
+        let state = State {
+            name: self.name,
+            observer,
+            decision,
+            span,
+            scope,
+            control: control.map(Branch::new),
+            experimental: experimental.map(Branch::new),
+            mismatch_handler: Some(self.mismatch_handler),
+            start: None,
+            index: 0,
+            first_diverging_index: None,
+        };
+
+        stream::unfold(state, |state| {
+            let span = state.span.clone();
+            let scope = state.scope.clone();
+            observer::scoped(scope, state.next_item()).instrument(span)
+        })
+    }
+}
+
+struct Branch<S> {
+    stream: Pin<Box<S>>,
+    scope: Option<Arc<dyn Scope>>,
+    len: usize,
+    duration: Option<Duration>,
+}
+
+impl<S> Branch<S>
+where
+    S: Stream,
+{
+    fn new(stream: S) -> Self {
+        Self {
+            stream: Box::pin(stream),
+            scope: None,
+            len: 0,
+            duration: None,
+        }
+    }
+
+    async fn next(&mut self, start: Instant) -> Option<S::Item> {
+        if self.duration.is_some() {
+            return None;
+        }
+
+        match observer::scoped(self.scope.clone(), self.stream.next()).await {
+            Some(item) => {
+                self.len += 1;
+                Some(item)
+            }
+            None => {
+                self.duration = Some(start.elapsed());
+                self.scope = None;
+                None
+            }
+        }
+    }
+}
+
+struct State<C, E, M> {
+    name: &'static str,
+    observer: StatsObserver,
+    decision: RolloutDecision,
+    span: Span,
+    scope: Option<Arc<dyn Scope>>,
+    control: Option<Branch<C>>,
+    experimental: Option<Branch<E>>,
+    mismatch_handler: Option<M>,
+    start: Option<Instant>,
+    index: usize,
+    first_diverging_index: Option<usize>,
+}
+
+impl<T, C, E, M> State<C, E, M>
+where
+    T: PartialEq,
+    C: Stream<Item = T>,
+    E: Stream<Item = T>,
+    M: StreamMismatchHandler,
+{
+    async fn next_item(mut self) -> Option<(T, Self)> {
+        let start = match self.start {
+            Some(start) => start,
+            None => self.start(),
+        };
+
+        let item = match (&mut self.control, &mut self.experimental) {
+            (Some(control), Some(experimental)) => {
+                let (control_item, experimental_item) =
+                    futures::join!(control.next(start), experimental.next(start));
+
+                if let (Some(c), Some(e)) = (&control_item, &experimental_item) {
+                    if c != e && self.first_diverging_index.is_none() {
+                        self.first_diverging_index = Some(self.index);
+                    }
+                }
+
+                control_item
+            }
+            (Some(control), None) => control.next(start).await,
+            (None, Some(experimental)) => experimental.next(start).await,
+            (None, None) => None,
+        };
+
+        match item {
+            Some(item) => {
+                self.index += 1;
+                Some((item, self))
+            }
+            None => {
+                self.finish();
+                None
+            }
+        }
+    }
+
+    /// Start timing the branches on the first poll of the stream
+    fn start(&mut self) -> Instant {
+        if let Some(control) = &mut self.control {
+            control.scope = self.observer.branch_scope(self.name, "control");
+        }
+        if let Some(experimental) = &mut self.experimental {
+            experimental.scope = self.observer.branch_scope(self.name, "experimental");
+        }
+
+        *self.start.insert(Instant::now())
+    }
+
+    fn finish(mut self) {
+        if let Some(Branch {
+            duration: Some(duration),
+            ..
+        }) = self.control
+        {
+            self.observer
+                .on_branch_duration(self.name, "control", duration);
+        }
+
+        if let Some(Branch {
+            duration: Some(duration),
+            ..
+        }) = self.experimental
+        {
+            self.observer
+                .on_branch_duration(self.name, "experimental", duration);
+        }
+
+        let mut report = Report {
+            decision: self.decision,
+            control_duration: self.control.as_ref().and_then(|c| c.duration),
+            experimental_duration: self.experimental.as_ref().and_then(|e| e.duration),
+            outcome: Outcome::Ignored,
+            returned: match self.control {
+                Some(_) => Returned::Control,
+                None => Returned::Experimental,
+            },
+        };
+
+        if let (Some(control), Some(experimental)) = (&self.control, &self.experimental) {
+            report.outcome = Outcome::Match;
+
+            if let (Some(control), Some(experimental)) = (control.duration, experimental.duration) {
+                latency::record(self.name, control, experimental);
+                self.observer
+                    .on_latency_delta(self.name, control, experimental);
+            }
+
+            if self.first_diverging_index.is_some() || control.len != experimental.len {
+                report.outcome = Outcome::Mismatch;
+                self.observer.on_mismatch(self.name, None);
+
+                let mismatch = StreamMismatch {
+                    first_diverging_index: self.first_diverging_index,
+                    control_len: control.len,
+                    experimental_len: experimental.len,
+                    control_duration: control.duration.unwrap_or_default(),
+                    experimental_duration: experimental.duration,
+                };
+
+                if let Some(handler) = self.mismatch_handler.take() {
+                    handler.on_mismatch(mismatch);
+                }
+            }
+        }
+
+        self.observer.on_run_end(self.name, &report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RecordingObserver;
+
+    #[tokio::test]
+    async fn it_yields_control_items_and_reports_divergence() {
+        let mut seen = None;
+
+        let items = StreamExperiment::new("test")
+            .control(stream::iter(vec![1, 2, 3]))
+            .experimental(stream::iter(vec![1, 5, 3, 4]))
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|mismatch| seen = Some(mismatch))
+            .run()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items, vec![1, 2, 3]);
+
+        let mismatch = seen.expect("mismatch handler was not called");
+        assert_eq!(mismatch.first_diverging_index, Some(1));
+        assert_eq!(mismatch.control_len, 3);
+        assert_eq!(mismatch.experimental_len, 4);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_report_matching_streams() {
+        let mut seen = false;
+
+        let items = StreamExperiment::new("test")
+            .control(stream::iter(vec![1, 2, 3]))
+            .experimental(stream::iter(vec![1, 2, 3]))
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|_| seen = true)
+            .run()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items, vec![1, 2, 3]);
+        assert!(!seen);
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_end_of_the_run() {
+        let recorder = RecordingObserver::new();
+
+        StreamExperiment::new("stream run end")
+            .control(stream::iter(vec![1, 2, 3]))
+            .experimental(stream::iter(vec![1, 2, 3]))
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .observer(recorder.shared())
+            .run()
+            .collect::<Vec<_>>()
+            .await;
+
+        let runs = recorder.runs("stream run end");
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].outcome, Outcome::Match);
+        assert_eq!(runs[0].returned, Returned::Control);
+        assert!(runs[0].experimental_duration.is_some());
+
+        let stats = crate::stats::experiment("stream run end").unwrap();
+        assert_eq!(stats.matches, 1);
+        assert_eq!(stats.mismatches, 0);
+    }
+
+    #[tokio::test]
+    async fn it_reports_length_mismatch_without_diverging_index() {
+        let mut seen = None;
+
+        let items = StreamExperiment::new("test")
+            .control(stream::iter(vec![1, 2, 3]))
+            .experimental(stream::iter(vec![1, 2]))
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|mismatch| seen = Some(mismatch))
+            .run()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items, vec![1, 2, 3]);
+
+        let mismatch = seen.expect("mismatch handler was not called");
+        assert_eq!(mismatch.first_diverging_index, None);
+        assert_eq!(mismatch.control_len, 3);
+        assert_eq!(mismatch.experimental_len, 2);
+    }
+
+    #[tokio::test]
+    async fn it_runs_experimental_stream_and_ignores_control() {
+        let items = StreamExperiment::new("test")
+            .control(stream::iter(vec![1, 2, 3]))
+            .experimental(stream::iter(vec![4, 5]))
+            .rollout_strategy(RolloutDecision::UseExperimental)
+            .run()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items, vec![4, 5]);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_wait_for_longer_experimental_streams() {
+        let mut seen = None;
+
+        let items = StreamExperiment::new("test")
+            .control(stream::iter(vec![1]))
+            .experimental(stream::repeat(1))
+            .rollout_strategy(RolloutDecision::UseExperimentalAndCompare)
+            .on_mismatch(|mismatch| seen = Some(mismatch))
+            .run()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items, vec![1]);
+
+        let mismatch = seen.expect("mismatch handler was not called");
+        assert_eq!(mismatch.first_diverging_index, None);
+        assert_eq!(mismatch.control_len, 1);
+        assert_eq!(mismatch.experimental_len, 2);
+        assert_eq!(mismatch.experimental_duration, None);
+    }
+}
@@ -0,0 +1,494 @@
+//! Helpers for testing applications which contain experiments. Only available
+//! with the `testing` cargo feature.
+//!
+//! ```
+//! use thesis::{testing, Experiment, RolloutDecision};
+//!
+//! # tokio_test::block_on(async {
+//! let recorder = testing::RecordingObserver::new();
+//! let _guard = testing::force_decision_for(
+//!     "load_data_from_db => load_data_from_redis",
+//!     RolloutDecision::UseExperimentalAndCompare,
+//! );
+//!
+//! let result = Experiment::new("load_data_from_db => load_data_from_redis")
+//!     .control(async { 4 })
+//!     .experimental(async { 4 })
+//!     .rollout_strategy(RolloutDecision::UseControl)
+//!     .observer(recorder.shared())
+//!     .run()
+//!     .await;
+//!
+//! assert_eq!(result, 4);
+//! assert_eq!(recorder.runs("load_data_from_db => load_data_from_redis").len(), 1);
+//! recorder.assert_no_mismatches();
+//! # });
+//! ```
+
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::observer::{BranchOutcome, ExperimentObserver};
+use crate::report::Report;
+use crate::rollout::RolloutDecision;
+
+struct Override {
+    id: usize,
+    name: Option<&'static str>,
+    decision: RolloutDecision,
+}
+
+thread_local! {
+    static THREAD_OVERRIDES: RefCell<Vec<Override>> = const { RefCell::new(Vec::new()) };
+}
+
+static GLOBAL_OVERRIDES: Mutex<Vec<Override>> = Mutex::new(Vec::new());
+static ACTIVE_GLOBAL_OVERRIDES: AtomicUsize = AtomicUsize::new(0);
+static NEXT_OVERRIDE_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn global_overrides() -> MutexGuard<'static, Vec<Override>> {
+    GLOBAL_OVERRIDES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Guard returned by the `force_*` functions. The forced decision stays in
+/// place until the guard is dropped. Guards can't be sent to other threads, so
+/// a decision forced for the current thread is removed from the same thread.
+#[must_use = "the decision is only forced until the guard is dropped"]
+pub struct DecisionOverride {
+    id: usize,
+    global: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for DecisionOverride {
+    fn drop(&mut self) {
+        if self.global {
+            global_overrides().retain(|o| o.id != self.id);
+            ACTIVE_GLOBAL_OVERRIDES.fetch_sub(1, Ordering::SeqCst);
+        } else {
+            // The thread-local may already be gone if the guard is dropped
+            // while the thread is exiting
+            let _ = THREAD_OVERRIDES
+                .try_with(|overrides| overrides.borrow_mut().retain(|o| o.id != self.id));
+        }
+    }
+}
+
+fn push_override(
+    global: bool,
+    name: Option<&'static str>,
+    decision: RolloutDecision,
+) -> DecisionOverride {
+    let id = NEXT_OVERRIDE_ID.fetch_add(1, Ordering::SeqCst);
+    let o = Override { id, name, decision };
+
+    if global {
+        global_overrides().push(o);
+        ACTIVE_GLOBAL_OVERRIDES.fetch_add(1, Ordering::SeqCst);
+    } else {
+        THREAD_OVERRIDES.with(|overrides| overrides.borrow_mut().push(o));
+    }
+
+    DecisionOverride {
+        id,
+        global,
+        _not_send: PhantomData,
+    }
+}
+
+/// Make every experiment run on the current thread use the given decision
+/// instead of asking its rollout strategy, until the returned guard is
+/// dropped. Tests running in parallel on other threads are not affected. This
+/// covers experiments run from a `#[tokio::test]`, which runs on a single
+/// thread by default. For experiments run on other threads, such as on a
+/// multi-threaded runtime, use `force_global_decision`.
+pub fn force_decision(decision: RolloutDecision) -> DecisionOverride {
+    push_override(false, None, decision)
+}
+
+/// Make the experiment with the given name use the given decision instead of
+/// asking its rollout strategy when it runs on the current thread, until the
+/// returned guard is dropped
+pub fn force_decision_for(name: &'static str, decision: RolloutDecision) -> DecisionOverride {
+    push_override(false, Some(name), decision)
+}
+
+/// Like `force_decision`, but for experiments running on every thread. Tests
+/// using it should not run in parallel with other tests which run
+/// experiments.
+pub fn force_global_decision(decision: RolloutDecision) -> DecisionOverride {
+    push_override(true, None, decision)
+}
+
+/// Like `force_decision_for`, but for the named experiment running on every
+/// thread
+pub fn force_global_decision_for(
+    name: &'static str,
+    decision: RolloutDecision,
+) -> DecisionOverride {
+    push_override(true, Some(name), decision)
+}
+
+fn find_override(overrides: &[Override], name: &'static str) -> Option<RolloutDecision> {
+    overrides
+        .iter()
+        .rev()
+        .find(|o| o.name.is_none() || o.name == Some(name))
+        .map(|o| o.decision)
+}
+
+/// The decision forced for the named experiment, if any. Decisions forced for
+/// the current thread win over global ones, and when several overrides of the
+/// same kind apply, the most recently created one wins.
+pub(crate) fn forced_decision(name: &'static str) -> Option<RolloutDecision> {
+    let decision = THREAD_OVERRIDES
+        .try_with(|overrides| find_override(&overrides.borrow(), name))
+        .ok()
+        .flatten();
+
+    if decision.is_some() || ACTIVE_GLOBAL_OVERRIDES.load(Ordering::SeqCst) == 0 {
+        return decision;
+    }
+
+    find_override(&global_overrides(), name)
+}
+
+/// An event captured by `RecordingObserver`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The experiment started running, with the labels set with
+    /// `Experiment::label`
+    RunStart {
+        name: &'static str,
+        labels: Vec<(&'static str, String)>,
+    },
+
+    /// The rollout strategy made a decision
+    Decision {
+        name: &'static str,
+        decision: RolloutDecision,
+    },
+
+    /// The experimental method was skipped
+    Skipped {
+        name: &'static str,
+        reason: &'static str,
+    },
+
+    /// The control or experimental method finished
+    BranchDuration {
+        name: &'static str,
+        kind: &'static str,
+        duration: Duration,
+    },
+
+    /// The control or experimental method returned a `Result`. `error` is the
+    /// `Display` representation of the error, if it returned `Err`, and
+    /// `category` is the category the experiment classified the error as.
+    Outcome {
+        name: &'static str,
+        kind: &'static str,
+        error: Option<String>,
+        category: Option<&'static str>,
+    },
+
+    /// The control and experimental methods produced different values.
+    /// `category` is the category the experiment classified the mismatch as,
+    /// if it classifies mismatches.
+    Mismatch {
+        name: &'static str,
+        category: Option<&'static str>,
+    },
+
+    /// The experimental method failed and the control method was run instead.
+    /// `reason` is the `Display` representation of the error or panic.
+    Fallback { name: &'static str, reason: String },
+
+    /// The method of the given kind won a race
+    RaceWon {
+        name: &'static str,
+        kind: &'static str,
+    },
+
+    /// The experiment finished running
+    RunEnd { name: &'static str, report: Report },
+}
+
+impl Event {
+    /// The name of the experiment which produced this event
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::RunStart { name, .. }
+            | Event::Decision { name, .. }
+            | Event::Skipped { name, .. }
+            | Event::BranchDuration { name, .. }
+            | Event::Outcome { name, .. }
+            | Event::Mismatch { name, .. }
+            | Event::Fallback { name, .. }
+            | Event::RaceWon { name, .. }
+            | Event::RunEnd { name, .. } => name,
+        }
+    }
+}
+
+/// An observer which records every event it sees, so tests can make
+/// assertions about them. Clones share the same recording.
+#[derive(Clone, Default)]
+pub struct RecordingObserver {
+    events: Arc<Mutex<Vec<Event>>>,
+
+    /// Labels of the run this copy was made for by `with_labels`
+    labels: Vec<(&'static str, String)>,
+}
+
+impl RecordingObserver {
+    /// Create an observer with no recorded events
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// This observer as a shared handle, to pass to `Experiment::observer`
+    pub fn shared(&self) -> Arc<dyn ExperimentObserver> {
+        Arc::new(self.clone())
+    }
+
+    fn record(&self, event: Event) {
+        self.events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(event);
+    }
+
+    /// Every event recorded so far, in the order they happened
+    pub fn events(&self) -> Vec<Event> {
+        self.events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// The reports of every finished run of the named experiment
+    pub fn runs(&self, name: &str) -> Vec<Report> {
+        self.events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::RunEnd { name: n, report } if n == name => Some(report),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The names of the experiments which reported a mismatch, once per
+    /// mismatch
+    pub fn mismatches(&self) -> Vec<&'static str> {
+        self.events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Mismatch { name, .. } => Some(name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Forget every event recorded so far
+    pub fn clear(&self) {
+        self.events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clear();
+    }
+
+    /// Panic if any experiment reported a mismatch
+    #[track_caller]
+    pub fn assert_no_mismatches(&self) {
+        let mismatches = self.mismatches();
+
+        assert!(
+            mismatches.is_empty(),
+            "expected no experiment mismatches, found mismatches in {:?}",
+            mismatches
+        );
+    }
+
+    /// Panic if the named experiment did not report a mismatch
+    #[track_caller]
+    pub fn assert_mismatch(&self, name: &'static str) {
+        assert!(
+            self.mismatches().contains(&name),
+            "expected a mismatch in experiment {:?}",
+            name
+        );
+    }
+}
+
+impl ExperimentObserver for RecordingObserver {
+    fn with_labels(
+        &self,
+        labels: &[(&'static str, String)],
+    ) -> Option<Arc<dyn ExperimentObserver>> {
+        Some(Arc::new(RecordingObserver {
+            events: self.events.clone(),
+            labels: labels.to_vec(),
+        }))
+    }
+
+    fn on_run_start(&self, name: &'static str) {
+        self.record(Event::RunStart {
+            name,
+            labels: self.labels.clone(),
+        });
+    }
+
+    fn on_decision(&self, name: &'static str, decision: RolloutDecision) {
+        self.record(Event::Decision { name, decision });
+    }
+
+    fn on_skipped(&self, name: &'static str, reason: &'static str) {
+        self.record(Event::Skipped { name, reason });
+    }
+
+    fn on_branch_duration(&self, name: &'static str, kind: &'static str, duration: Duration) {
+        self.record(Event::BranchDuration {
+            name,
+            kind,
+            duration,
+        });
+    }
+
+    fn on_outcome(&self, name: &'static str, kind: &'static str, outcome: BranchOutcome<'_>) {
+        let (error, category) = match outcome {
+            BranchOutcome::Ok => (None, None),
+            BranchOutcome::Error { error, category } => (Some(error.to_string()), category),
+        };
+
+        self.record(Event::Outcome {
+            name,
+            kind,
+            error,
+            category,
+        });
+    }
+
+    fn on_mismatch(&self, name: &'static str, category: Option<&'static str>) {
+        self.record(Event::Mismatch { name, category });
+    }
+
+    fn on_fallback(&self, name: &'static str, reason: &dyn Display) {
+        self.record(Event::Fallback {
+            name,
+            reason: reason.to_string(),
+        });
+    }
+
+    fn on_race_won(&self, name: &'static str, kind: &'static str) {
+        self.record(Event::RaceWon { name, kind });
+    }
+
+    fn on_run_end(&self, name: &'static str, report: &Report) {
+        self.record(Event::RunEnd {
+            name,
+            report: report.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Outcome;
+    use crate::Experiment;
+
+    #[tokio::test]
+    async fn it_forces_decisions_until_the_guard_is_dropped() {
+        let recorder = RecordingObserver::new();
+
+        let guard = force_decision_for("forced", RolloutDecision::UseExperimental);
+        let value = Experiment::new("forced")
+            .control(async { 1 })
+            .experimental(async { 2 })
+            .rollout_strategy(RolloutDecision::UseControl)
+            .observer(recorder.shared())
+            .run()
+            .await;
+        assert_eq!(value, 2);
+
+        drop(guard);
+        let value = Experiment::new("forced")
+            .control(async { 1 })
+            .experimental(async { 2 })
+            .rollout_strategy(RolloutDecision::UseControl)
+            .observer(recorder.shared())
+            .run()
+            .await;
+        assert_eq!(value, 1);
+
+        let decisions = recorder
+            .runs("forced")
+            .into_iter()
+            .map(|report| report.decision)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decisions,
+            vec![
+                RolloutDecision::UseExperimental,
+                RolloutDecision::UseControl
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_records_mismatches() {
+        let recorder = RecordingObserver::new();
+        let _guard = force_decision_for("recorded", RolloutDecision::UseExperimentalAndCompare);
+
+        Experiment::new("recorded")
+            .control(async { 1 })
+            .experimental(async { 2 })
+            .rollout_strategy(RolloutDecision::UseControl)
+            .observer(recorder.shared())
+            .run()
+            .await;
+
+        recorder.assert_mismatch("recorded");
+        assert_eq!(recorder.runs("recorded")[0].outcome, Outcome::Mismatch);
+
+        let result = std::panic::catch_unwind(|| recorder.assert_no_mismatches());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_only_forces_decisions_on_the_current_thread() {
+        let _guard = force_decision_for("threaded", RolloutDecision::UseExperimental);
+        assert_eq!(
+            forced_decision("threaded"),
+            Some(RolloutDecision::UseExperimental)
+        );
+
+        let other_thread = std::thread::spawn(|| forced_decision("threaded"))
+            .join()
+            .unwrap();
+        assert_eq!(other_thread, None);
+
+        let global = force_global_decision_for("threaded", RolloutDecision::UseControl);
+        let other_thread = std::thread::spawn(|| forced_decision("threaded"))
+            .join()
+            .unwrap();
+        assert_eq!(other_thread, Some(RolloutDecision::UseControl));
+        assert_eq!(
+            forced_decision("threaded"),
+            Some(RolloutDecision::UseExperimental)
+        );
+
+        drop(global);
+        let other_thread = std::thread::spawn(|| forced_decision("threaded"))
+            .join()
+            .unwrap();
+        assert_eq!(other_thread, None);
+    }
+}
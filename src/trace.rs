@@ -0,0 +1,33 @@
+//! Stand-ins for the parts of `tracing` used by this crate, which do nothing
+//! when the `tracing` feature is disabled.
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{Instrument, Span};
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) trait Instrument: Sized {
+    fn instrument(self, _span: Span) -> Self {
+        self
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl<T> Instrument for T {}
+
+macro_rules! info_span {
+    ($($args:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = ::tracing::info_span!($($args)*);
+
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::Span;
+
+        span
+    }};
+}
+
+pub(crate) use info_span;
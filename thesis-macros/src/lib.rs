@@ -0,0 +1,604 @@
+//! Procedural macros for the `thesis` crate. These are re-exported from
+//! `thesis` when its `macros` feature is enabled, and should be used from
+//! there.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    Expr, FnArg, ItemFn, ItemTrait, Lit, MetaNameValue, Pat, PatType, Receiver, ReturnType,
+    Signature, Token, TraitItem, Type,
+};
+
+struct ExperimentArgs {
+    name: Option<Expr>,
+    experimental: Expr,
+    rollout: Expr,
+    on_mismatch: Option<Expr>,
+    run: Option<RunKind>,
+}
+
+/// How an experiment is run, given with the `run` argument to override the
+/// detection of `Result` return types
+#[derive(Clone, Copy)]
+enum RunKind {
+    Plain,
+    Result,
+}
+
+impl ExperimentArgs {
+    fn parse(args: TokenStream) -> syn::Result<Self> {
+        let metas = Punctuated::<MetaNameValue, Token![,]>::parse_terminated.parse(args)?;
+
+        let mut name = None;
+        let mut experimental = None;
+        let mut rollout = None;
+        let mut on_mismatch = None;
+        let mut run = None;
+
+        for meta in metas {
+            let slot = if meta.path.is_ident("name") {
+                &mut name
+            } else if meta.path.is_ident("experimental") {
+                &mut experimental
+            } else if meta.path.is_ident("rollout") {
+                &mut rollout
+            } else if meta.path.is_ident("on_mismatch") {
+                &mut on_mismatch
+            } else if meta.path.is_ident("run") {
+                &mut run
+            } else {
+                return Err(syn::Error::new(
+                    meta.path.span(),
+                    "expected one of `name`, `experimental`, `rollout`, `on_mismatch` or `run`",
+                ));
+            };
+
+            if slot.is_some() {
+                return Err(syn::Error::new(meta.path.span(), "duplicate argument"));
+            }
+
+            *slot = Some(meta.value);
+        }
+
+        if let Some(Expr::Lit(lit)) = &name {
+            if !matches!(lit.lit, Lit::Str(_)) {
+                return Err(syn::Error::new(
+                    lit.span(),
+                    "`name` must be a string literal",
+                ));
+            }
+        }
+
+        let run = run.map(run_kind).transpose()?;
+
+        Ok(Self {
+            name,
+            experimental: experimental.ok_or_else(|| {
+                syn::Error::new(Span::call_site(), "missing `experimental` argument")
+            })?,
+            rollout: rollout
+                .ok_or_else(|| syn::Error::new(Span::call_site(), "missing `rollout` argument"))?,
+            on_mismatch,
+            run,
+        })
+    }
+}
+
+fn run_kind(run: Expr) -> syn::Result<RunKind> {
+    if let Expr::Lit(lit) = &run {
+        if let Lit::Str(kind) = &lit.lit {
+            match &*kind.value() {
+                "plain" => return Ok(RunKind::Plain),
+                "result" => return Ok(RunKind::Result),
+                _ => {}
+            }
+        }
+    }
+
+    Err(syn::Error::new(
+        run.span(),
+        "`run` must be \"plain\" or \"result\"",
+    ))
+}
+
+/// Turn a function into an experiment. The original body of the function is
+/// used as the control, and the function given as `experimental` is called
+/// with the same arguments as the experimental. Works with both `async fn` and
+/// plain `fn`. Functions whose return type is a path ending in `Result` are run
+/// with `run_result`, which requires the error type to implement `Display`.
+/// This includes aliases named `Result`, use `run = "plain"` to run those with
+/// `run` instead.
+///
+/// Arguments:
+///
+/// - `experimental` (required) - path of the function to use as the
+///   experimental. It must take the same arguments and return the same type as
+///   the annotated function.
+/// - `rollout` (required) - expression for the rollout strategy
+/// - `name` - name of the experiment, defaults to the module path and name of
+///   the function
+/// - `on_mismatch` - expression for the mismatch handler closure
+/// - `run` - `"plain"` or `"result"`, to run the experiment with `run` or
+///   `run_result` whatever the return type is
+///
+/// The rollout decision is made before the experimental function is called,
+/// and the arguments are only cloned for it when the decision runs it. They
+/// must implement `Clone`. A `self` receiver must be `&self`, and is passed as
+/// the first argument to the experimental function.
+///
+/// ```
+/// async fn load_data_from_redis(id: i32) -> i32 {
+///     id
+/// }
+///
+/// #[thesis::experiment(
+///     name = "load_data_from_db => load_data_from_redis",
+///     experimental = load_data_from_redis,
+///     rollout = thesis::rollout::Percent::new(0.5),
+/// )]
+/// async fn load_data_from_db(id: i32) -> i32 {
+///     id
+/// }
+/// ```
+///
+/// Arguments aren't cloned when only the control runs, and `run = "plain"`
+/// runs functions returning a `Result` alias whose error type isn't `Display`:
+///
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use thesis::RolloutDecision;
+///
+/// static CLONES: AtomicUsize = AtomicUsize::new(0);
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Query(u32);
+///
+/// impl Clone for Query {
+///     fn clone(&self) -> Self {
+///         CLONES.fetch_add(1, Ordering::SeqCst);
+///         Query(self.0)
+///     }
+/// }
+///
+/// #[derive(Debug, PartialEq)]
+/// struct NotDisplay;
+///
+/// type Result<T> = std::result::Result<T, NotDisplay>;
+///
+/// fn new_lookup(query: Query, compare: bool) -> Result<u32> {
+///     Ok(query.0)
+/// }
+///
+/// #[thesis::experiment(
+///     experimental = new_lookup,
+///     rollout = if compare {
+///         RolloutDecision::UseExperimentalAndCompare
+///     } else {
+///         RolloutDecision::UseControl
+///     },
+///     run = "plain",
+/// )]
+/// fn lookup(query: Query, compare: bool) -> Result<u32> {
+///     Ok(query.0)
+/// }
+///
+/// assert_eq!(lookup(Query(4), false), Ok(4));
+/// assert_eq!(CLONES.load(Ordering::SeqCst), 0);
+///
+/// assert_eq!(lookup(Query(4), true), Ok(4));
+/// assert_eq!(CLONES.load(Ordering::SeqCst), 1);
+/// ```
+#[proc_macro_attribute]
+pub fn experiment(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = match ExperimentArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let item = syn::parse_macro_input!(item as ItemFn);
+
+    match expand_experiment(args, item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_experiment(args: ExperimentArgs, item: ItemFn) -> syn::Result<TokenStream2> {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = item;
+
+    let fn_name = &sig.ident;
+    let name = match args.name {
+        Some(name) => quote!(#name),
+        None => quote!(concat!(module_path!(), "::", stringify!(#fn_name))),
+    };
+
+    let mut clones = Vec::new();
+    let mut experimental_args = Vec::new();
+
+    for input in &sig.inputs {
+        match input {
+            FnArg::Receiver(receiver) => {
+                shared_receiver(receiver)?;
+                experimental_args.push(quote!(self));
+            }
+            FnArg::Typed(typed) => {
+                let ident = arg_ident(typed)?;
+                let (clone, cloned) = clone_arg(ident);
+
+                clones.push(clone);
+                experimental_args.push(quote!(#cloned));
+            }
+        }
+    }
+
+    let experimental = &args.experimental;
+    let rollout = &args.rollout;
+    let on_mismatch = args
+        .on_mismatch
+        .as_ref()
+        .map(|on_mismatch| quote!(.on_mismatch(#on_mismatch)));
+
+    let run = run_method(&sig, args.run);
+    let output = output_type(&sig.output);
+
+    let experimental_call = quote!(#experimental(#(#experimental_args),*));
+    let (build_experimental, experimental) = lazy_experimental(&sig, &clones, experimental_call);
+    let control = if sig.asyncness.is_some() {
+        quote!(.control(async move #block))
+    } else {
+        quote!(.control_fn(move || #block))
+    };
+
+    let body = quote! {
+        let __thesis_decision = ::thesis::rollout::decide(#name, &#rollout);
+        #build_experimental
+
+        ::thesis::Experiment::<#output, _, _, _, _>::new(#name)
+            #control
+            #experimental
+            .rollout_strategy(__thesis_decision)
+            #on_mismatch
+            .#run
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis #sig {
+            #body
+        }
+    })
+}
+
+/// Generate a `Dual{Trait}<Old, New, R, M>` struct implementing the annotated
+/// trait, where every method runs as an experiment named `Trait::method`. The
+/// `Old` implementation is used as the control, the `New` implementation as the
+/// experimental, and every method shares the rollout strategy `R` and the
+/// mismatch handler `M`. Methods returning a `Result` are run with
+/// `run_result`.
+///
+/// Every method must take `&self`, must not be generic, and must return an
+/// owned type. Arguments are cloned for the `New` implementation when the
+/// rollout decision runs it, so they must implement `Clone`. Methods whose
+/// return type is a path ending in `Result` require the error type to implement
+/// `Display`. The mismatch handler must implement `Clone` and
+/// `MismatchHandler` for the return type of every method.
+///
+/// ```
+/// use thesis::RolloutDecision;
+///
+/// #[thesis::dual]
+/// trait UserRepo {
+///     fn name(&self, id: u64) -> String;
+/// }
+///
+/// struct Postgres;
+/// struct Dynamo;
+///
+/// impl UserRepo for Postgres {
+///     fn name(&self, id: u64) -> String {
+///         format!("user {}", id)
+///     }
+/// }
+///
+/// impl UserRepo for Dynamo {
+///     fn name(&self, id: u64) -> String {
+///         format!("user {}", id)
+///     }
+/// }
+///
+/// let repo = DualUserRepo::new(Postgres, Dynamo, RolloutDecision::UseExperimentalAndCompare);
+/// assert_eq!(repo.name(4), "user 4");
+/// ```
+#[proc_macro_attribute]
+pub fn dual(args: TokenStream, item: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return syn::Error::new(Span::call_site(), "`dual` takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let item = syn::parse_macro_input!(item as ItemTrait);
+
+    match expand_dual(item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_dual(item: ItemTrait) -> syn::Result<TokenStream2> {
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            item.generics.span(),
+            "dual traits can't have generic parameters",
+        ));
+    }
+
+    let trait_name = &item.ident;
+    let vis = &item.vis;
+    let dual = format_ident!("Dual{}", trait_name);
+
+    let mut methods = Vec::new();
+    let mut outputs = Vec::new();
+
+    for trait_item in &item.items {
+        let method = match trait_item {
+            TraitItem::Fn(method) => method,
+            other => {
+                return Err(syn::Error::new(
+                    other.span(),
+                    "dual traits can only contain methods",
+                ))
+            }
+        };
+
+        let sig = &method.sig;
+        if !sig.generics.params.is_empty() {
+            return Err(syn::Error::new(
+                sig.generics.span(),
+                "dual trait methods can't have generic parameters",
+            ));
+        }
+
+        let mut inputs = sig.inputs.iter();
+        match inputs.next() {
+            Some(FnArg::Receiver(receiver)) => shared_receiver(receiver)?,
+            _ => {
+                return Err(syn::Error::new(
+                    sig.span(),
+                    "dual trait methods must take `&self`",
+                ))
+            }
+        }
+
+        let mut clones = Vec::new();
+        let mut control_args = Vec::new();
+        let mut experimental_args = Vec::new();
+
+        for input in inputs {
+            match input {
+                FnArg::Typed(typed) => {
+                    let ident = arg_ident(typed)?;
+                    let (clone, cloned) = clone_arg(ident);
+
+                    clones.push(clone);
+                    control_args.push(ident);
+                    experimental_args.push(cloned);
+                }
+                FnArg::Receiver(receiver) => {
+                    return Err(syn::Error::new(receiver.span(), "unexpected receiver"))
+                }
+            }
+        }
+
+        let method_name = &sig.ident;
+        let output = output_type(&sig.output);
+        let run = run_method(sig, None);
+
+        let control = quote!(self.old.#method_name(#(#control_args),*));
+        let experimental_call = quote!(self.new.#method_name(#(#experimental_args),*));
+        let (build_experimental, experimental) = lazy_experimental(sig, &clones, experimental_call);
+
+        let control = if sig.asyncness.is_some() {
+            quote!(.control(#control))
+        } else {
+            quote!(.control_fn(move || #control))
+        };
+
+        methods.push(quote! {
+            #sig {
+                let __thesis_name = concat!(stringify!(#trait_name), "::", stringify!(#method_name));
+                let __thesis_decision =
+                    ::thesis::rollout::decide(__thesis_name, &self.rollout_strategy);
+                #build_experimental
+
+                ::thesis::Experiment::<#output, _, _, _, _>::new(__thesis_name)
+                #control
+                #experimental
+                .rollout_strategy(__thesis_decision)
+                .mismatch_handler(::core::clone::Clone::clone(&self.mismatch_handler))
+                .#run
+            }
+        });
+        outputs.push(output);
+    }
+
+    let doc = format!(
+        "Runs every method of `{0}` as an experiment, using `Old` as the control \
+         and `New` as the experimental. Generated by `#[thesis::dual]`.",
+        trait_name
+    );
+
+    Ok(quote! {
+        #item
+
+        #[doc = #doc]
+        #vis struct #dual<Old, New, R, M = ::thesis::mismatch::AlwaysControl> {
+            old: Old,
+            new: New,
+            rollout_strategy: R,
+            mismatch_handler: M,
+        }
+
+        impl<Old, New, R> #dual<Old, New, R> {
+            /// Create a new dual implementation, which resolves mismatches by
+            /// using the value from `old`
+            #vis fn new(old: Old, new: New, rollout_strategy: R) -> Self {
+                Self {
+                    old,
+                    new,
+                    rollout_strategy,
+                    mismatch_handler: ::thesis::mismatch::AlwaysControl,
+                }
+            }
+        }
+
+        impl<Old, New, R, M> #dual<Old, New, R, M> {
+            /// Resolve mismatches in every method with the given handler
+            #vis fn on_mismatch<NM>(self, mismatch_handler: NM) -> #dual<Old, New, R, NM> {
+                #dual {
+                    old: self.old,
+                    new: self.new,
+                    rollout_strategy: self.rollout_strategy,
+                    mismatch_handler,
+                }
+            }
+        }
+
+        impl<Old, New, R, M> #trait_name for #dual<Old, New, R, M>
+        where
+            Old: #trait_name,
+            New: #trait_name,
+            R: ::thesis::RolloutStrategy,
+            M: ::core::clone::Clone #(+ ::thesis::MismatchHandler<#outputs>)*,
+        {
+            #(#methods)*
+        }
+    })
+}
+
+/// Receivers are shared between the control and experimental, so only `&self`
+/// is supported
+fn shared_receiver(receiver: &Receiver) -> syn::Result<()> {
+    if receiver.reference.is_none() || receiver.mutability.is_some() {
+        return Err(syn::Error::new(
+            receiver.span(),
+            "experiments only support `&self` receivers",
+        ));
+    }
+
+    Ok(())
+}
+
+fn arg_ident(typed: &PatType) -> syn::Result<&Ident> {
+    match &*typed.pat {
+        Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none() => Ok(&pat.ident),
+        pat => Err(syn::Error::new(
+            pat.span(),
+            "experiment arguments must be plain identifiers",
+        )),
+    }
+}
+
+/// A statement cloning the argument for the experimental, and the identifier
+/// of the clone
+fn clone_arg(ident: &Ident) -> (TokenStream2, Ident) {
+    let cloned = format_ident!("__thesis_experimental_{}", ident);
+    let clone = quote! {
+        let #cloned = ::core::clone::Clone::clone(&#ident);
+    };
+
+    (clone, cloned)
+}
+
+/// Statements building the experimental once `__thesis_decision` has been
+/// made, which only clone the arguments and call the experimental when the
+/// decision runs it, and the builder method call passing it to the experiment
+fn lazy_experimental(
+    sig: &Signature,
+    clones: &[TokenStream2],
+    call: TokenStream2,
+) -> (TokenStream2, TokenStream2) {
+    let (build, experimental) = if sig.asyncness.is_some() {
+        (
+            call,
+            quote! {
+                .experimental(async move {
+                    match __thesis_experimental {
+                        ::core::option::Option::Some(experimental) => experimental.await,
+                        ::core::option::Option::None => unreachable!("the experimental was not built"),
+                    }
+                })
+            },
+        )
+    } else {
+        (
+            quote!(move || #call),
+            quote! {
+                .experimental_fn(move || match __thesis_experimental {
+                    ::core::option::Option::Some(experimental) => experimental(),
+                    ::core::option::Option::None => unreachable!("the experimental was not built"),
+                })
+            },
+        )
+    };
+
+    let build = quote! {
+        let __thesis_experimental = match __thesis_decision {
+            ::thesis::RolloutDecision::UseControl => ::core::option::Option::None,
+            _ => {
+                #(#clones)*
+                ::core::option::Option::Some(#build)
+            }
+        };
+    };
+
+    (build, experimental)
+}
+
+fn output_type(output: &ReturnType) -> TokenStream2 {
+    match output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    }
+}
+
+/// The method used to run an experiment on a function with the given
+/// signature, unless the `run` argument says otherwise
+fn run_method(sig: &Signature, kind: Option<RunKind>) -> TokenStream2 {
+    let result = match kind {
+        Some(RunKind::Plain) => false,
+        Some(RunKind::Result) => true,
+        None => returns_result(&sig.output),
+    };
+
+    match (sig.asyncness.is_some(), result) {
+        (true, true) => quote!(run_result().await),
+        (true, false) => quote!(run().await),
+        (false, true) => quote!(run_result_sync()),
+        (false, false) => quote!(run_sync()),
+    }
+}
+
+fn returns_result(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Default => false,
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .map(|segment| segment.ident == "Result")
+                .unwrap_or(false),
+            _ => false,
+        },
+    }
+}
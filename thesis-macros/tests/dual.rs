@@ -0,0 +1,83 @@
+use std::fmt;
+use thesis::{Mismatch, MismatchHandler, RolloutDecision};
+
+#[derive(Debug, PartialEq)]
+struct RepoError;
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "repo error")
+    }
+}
+
+#[thesis::dual]
+trait UserRepo {
+    async fn email(&self, id: u64) -> Result<String, RepoError>;
+
+    fn count(&self) -> usize;
+}
+
+struct Postgres;
+
+impl UserRepo for Postgres {
+    async fn email(&self, id: u64) -> Result<String, RepoError> {
+        Ok(format!("{}@postgres", id))
+    }
+
+    fn count(&self) -> usize {
+        1
+    }
+}
+
+struct Dynamo;
+
+impl UserRepo for Dynamo {
+    async fn email(&self, id: u64) -> Result<String, RepoError> {
+        if id == 0 {
+            return Err(RepoError);
+        }
+
+        Ok(format!("{}@dynamo", id))
+    }
+
+    fn count(&self) -> usize {
+        2
+    }
+}
+
+#[derive(Clone)]
+struct PreferNew;
+
+impl<T> MismatchHandler<T> for PreferNew {
+    fn on_mismatch(self, mismatch: Mismatch<T>) -> T {
+        mismatch.experimental
+    }
+}
+
+#[tokio::test]
+async fn it_uses_the_old_implementation_by_default() {
+    let repo = DualUserRepo::new(Postgres, Dynamo, RolloutDecision::UseExperimentalAndCompare);
+
+    assert_eq!(repo.email(1).await, Ok("1@postgres".to_string()));
+    assert_eq!(repo.count(), 1);
+}
+
+#[tokio::test]
+async fn it_uses_the_shared_mismatch_handler() {
+    let repo = DualUserRepo::new(Postgres, Dynamo, RolloutDecision::UseExperimentalAndCompare)
+        .on_mismatch(PreferNew);
+
+    assert_eq!(repo.email(1).await, Ok("1@dynamo".to_string()));
+    assert_eq!(repo.count(), 2);
+
+    // An error from the experimental falls back to the control via run_result
+    assert_eq!(repo.email(0).await, Ok("0@postgres".to_string()));
+}
+
+#[tokio::test]
+async fn it_uses_the_shared_rollout_strategy() {
+    let repo = DualUserRepo::new(Postgres, Dynamo, RolloutDecision::UseExperimental);
+
+    assert_eq!(repo.email(1).await, Ok("1@dynamo".to_string()));
+    assert_eq!(repo.count(), 2);
+}
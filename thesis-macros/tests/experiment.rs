@@ -0,0 +1,82 @@
+use std::fmt;
+use thesis::RolloutDecision;
+
+async fn double_async(x: i32) -> i32 {
+    x * 2
+}
+
+#[thesis::experiment(
+    name = "double",
+    experimental = double_async,
+    rollout = RolloutDecision::UseExperimental
+)]
+async fn double_legacy(x: i32) -> i32 {
+    x + x + 1
+}
+
+#[tokio::test]
+async fn it_runs_the_experimental_async_fn() {
+    assert_eq!(double_legacy(2).await, 4);
+}
+
+#[derive(Debug, PartialEq)]
+struct ParseError;
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parse error")
+    }
+}
+
+fn parse_new(input: &str) -> Result<i32, ParseError> {
+    input.trim().parse().map_err(|_| ParseError)
+}
+
+#[thesis::experiment(
+    experimental = parse_new,
+    rollout = RolloutDecision::UseExperimentalAndCompare,
+    on_mismatch = |mismatch| mismatch.experimental
+)]
+fn parse_legacy(input: &str) -> Result<i32, ParseError> {
+    if input.is_empty() {
+        return Err(ParseError);
+    }
+
+    let value = input.parse().map_err(|_| ParseError)?;
+    Ok(value)
+}
+
+#[test]
+fn it_runs_sync_result_fns() {
+    assert_eq!(parse_legacy("42"), Ok(42));
+
+    // The control can't parse surrounding whitespace, so the mismatch handler
+    // picks the experimental value
+    assert_eq!(parse_legacy(" 42 "), Ok(42));
+    assert_eq!(parse_legacy(""), Err(ParseError));
+}
+
+struct Repo {
+    offset: i32,
+}
+
+impl Repo {
+    async fn lookup_new(&self, id: String) -> i32 {
+        id.len() as i32 + self.offset
+    }
+
+    #[thesis::experiment(
+        name = "Repo::lookup",
+        experimental = Self::lookup_new,
+        rollout = RolloutDecision::UseExperimentalAndCompare
+    )]
+    async fn lookup(&self, id: String) -> i32 {
+        id.len() as i32 + self.offset
+    }
+}
+
+#[tokio::test]
+async fn it_supports_methods() {
+    let repo = Repo { offset: 1 };
+    assert_eq!(repo.lookup("abc".to_string()).await, 4);
+}